@@ -0,0 +1,129 @@
+use async_channel::{unbounded, Receiver, Sender};
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::cell::RefCell;
+use wasm_bindgen::{prelude::*, JsCast};
+use web3::transports::eip_1193::Provider;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = window, js_name = addEventListener)]
+    fn add_window_listener(kind: &str, listener: &Closure<dyn FnMut(JsValue)>);
+
+    #[wasm_bindgen(js_namespace = window, js_name = dispatchEvent)]
+    fn dispatch_window_event(event: &DomEvent) -> bool;
+
+    #[wasm_bindgen(js_name = Event)]
+    type DomEvent;
+
+    #[wasm_bindgen(constructor, js_class = "Event")]
+    fn new(kind: &str) -> DomEvent;
+}
+
+thread_local! {
+    static PROVIDERS: RefCell<Vec<(String, Provider)>> = const { RefCell::new(vec![]) };
+}
+
+/// The announced provider with the reverse-DNS name `rdns`.
+pub(crate) fn provider(rdns: &str) -> Option<Provider> {
+    PROVIDERS.with(|providers| {
+        providers
+            .borrow()
+            .iter()
+            .find(|(id, _)| id == rdns)
+            .map(|(_, provider)| provider.clone())
+    })
+}
+
+/// EIP-6963 provider metadata.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct ProviderInfo {
+    pub uuid: String,
+    pub name: String,
+    /// Data uri of the wallet icon.
+    pub icon: String,
+    /// Reverse-DNS identifier, e.g. `io.metamask`.
+    pub rdns: String,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct ProviderAnnounced(pub ProviderInfo);
+
+/// The wallets discovered through EIP-6963, select one with `EthWallet::select_provider`.
+#[derive(Resource)]
+pub struct WalletProviders {
+    providers: Vec<ProviderInfo>,
+    tx: Sender<ProviderInfo>,
+    rx: Receiver<ProviderInfo>,
+}
+
+impl Default for WalletProviders {
+    fn default() -> Self {
+        let (tx, rx) = unbounded();
+        WalletProviders {
+            providers: vec![],
+            tx,
+            rx,
+        }
+    }
+}
+
+impl WalletProviders {
+    pub fn providers(&self) -> &[ProviderInfo] {
+        &self.providers
+    }
+
+    pub fn get(&self, rdns: &str) -> Option<&ProviderInfo> {
+        self.providers.iter().find(|p| p.rdns == rdns)
+    }
+
+    /// Ask the wallets to announce themselves again.
+    pub fn refresh(&self) {
+        if cfg!(target_arch = "wasm32") {
+            dispatch_window_event(&DomEvent::new("eip6963:requestProvider"));
+        }
+    }
+}
+
+pub(crate) fn discover_providers(providers: Res<WalletProviders>) {
+    // no window to listen on outside the browser
+    if cfg!(target_arch = "wasm32") {
+        listen(providers.tx.clone());
+        providers.refresh();
+    }
+}
+
+fn listen(tx: Sender<ProviderInfo>) {
+    let listener = Closure::wrap(Box::new(move |event: JsValue| {
+        if let Some((info, provider)) = parse_announcement(&event) {
+            PROVIDERS.with(|providers| {
+                let mut providers = providers.borrow_mut();
+                providers.retain(|(rdns, _)| *rdns != info.rdns);
+                providers.push((info.rdns.clone(), provider));
+            });
+            let _ = tx.try_send(info);
+        }
+    }) as Box<dyn FnMut(JsValue)>);
+    add_window_listener("eip6963:announceProvider", &listener);
+    listener.forget();
+}
+
+fn parse_announcement(event: &JsValue) -> Option<(ProviderInfo, Provider)> {
+    let detail = js_sys::Reflect::get(event, &"detail".into()).ok()?;
+    let info = js_sys::Reflect::get(&detail, &"info".into()).ok()?;
+    let provider = js_sys::Reflect::get(&detail, &"provider".into()).ok()?;
+    let info = js_sys::JSON::stringify(&info).ok()?.as_string()?;
+    let info = serde_json::from_str(&info).ok()?;
+    Some((info, provider.unchecked_into()))
+}
+
+pub(crate) fn recv_providers(
+    mut providers: ResMut<WalletProviders>,
+    mut events: EventWriter<ProviderAnnounced>,
+) {
+    while let Ok(info) = providers.rx.try_recv() {
+        providers.providers.retain(|p| p.rdns != info.rdns);
+        providers.providers.push(info.clone());
+        events.send(ProviderAnnounced(info));
+    }
+}
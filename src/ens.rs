@@ -0,0 +1,129 @@
+use rustc_hex::ToHex;
+use web3::{
+    ethabi::{self, ParamType, Token},
+    signing::keccak256,
+    types::{CallRequest, H160, H256},
+    Web3,
+};
+
+use crate::{EthWallet, WalletOp, WalletTransport, Web3Error};
+
+/// The ENS registry, at the same address on mainnet and the ENS testnets.
+pub const ENS_REGISTRY: H160 = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x2e, 0x07, 0x4e, 0xc6, 0x9a, 0x0d, 0xfb, 0x29, 0x97, 0xba,
+    0x6c, 0x7d, 0x2e, 0x1e,
+]);
+
+/// EIP-137 namehash of `name`. Labels are only lowercased, not fully
+/// UTS-46 normalized.
+pub fn namehash(name: &str) -> H256 {
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return H256(node);
+    }
+    for label in name.to_lowercase().rsplit('.') {
+        let mut buf = [0u8; 64];
+        buf[..32].copy_from_slice(&node);
+        buf[32..].copy_from_slice(&keccak256(label.as_bytes()));
+        node = keccak256(&buf);
+    }
+    H256(node)
+}
+
+fn node_call(method: &str, node: H256) -> Vec<u8> {
+    let mut data = ethabi::short_signature(method, &[ParamType::FixedBytes(32)]).to_vec();
+    data.extend(ethabi::encode(&[Token::FixedBytes(node.0.to_vec())]));
+    data
+}
+
+/// `eth_call` returning `None` when `to` has no code, i.e. ENS is not
+/// deployed on this chain.
+async fn call(
+    web3: &Web3<WalletTransport>,
+    to: H160,
+    data: Vec<u8>,
+) -> Result<Option<Vec<u8>>, Web3Error> {
+    let call = CallRequest {
+        to: Some(to),
+        data: Some(data.into()),
+        ..Default::default()
+    };
+    let bytes = web3.eth().call(call, None).await?.0;
+    Ok((!bytes.is_empty()).then_some(bytes))
+}
+
+fn decode_address(data: &[u8]) -> Option<H160> {
+    match ethabi::decode(&[ParamType::Address], data).ok()?.pop()? {
+        Token::Address(address) if !address.is_zero() => Some(address),
+        _ => None,
+    }
+}
+
+fn decode_name(data: &[u8]) -> Option<String> {
+    match ethabi::decode(&[ParamType::String], data).ok()?.pop()? {
+        Token::String(name) if !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
+async fn resolver(
+    web3: &Web3<WalletTransport>,
+    registry: H160,
+    node: H256,
+) -> Result<Option<H160>, Web3Error> {
+    let data = call(web3, registry, node_call("resolver", node)).await?;
+    Ok(data.as_deref().and_then(decode_address))
+}
+
+async fn resolve(
+    web3: &Web3<WalletTransport>,
+    registry: H160,
+    name: &str,
+) -> Result<Option<H160>, Web3Error> {
+    let node = namehash(name);
+    let Some(resolver) = resolver(web3, registry, node).await? else {
+        return Ok(None);
+    };
+    let data = call(web3, resolver, node_call("addr", node)).await?;
+    Ok(data.as_deref().and_then(decode_address))
+}
+
+impl EthWallet {
+    /// Resolve an ENS `name` (e.g. `vitalik.eth`) to its address, delivered
+    /// through `recv_ens_address` and `EnsResolved`. `None` if the name is not
+    /// registered or the chain has no ENS registry.
+    pub fn resolve_ens(&self, name: String) {
+        let registry = self.ens_registry;
+        self.spawn_read(WalletOp::Ens, self.ens_address_tx.clone(), move |web3| {
+            let name = name.clone();
+            async move {
+                let address = resolve(&web3, registry, &name).await?;
+                Ok(Some((name, address)))
+            }
+        });
+    }
+
+    /// Look up the primary ENS name of `address`, delivered through
+    /// `recv_ens_name` and `EnsReverseResolved`. The name is only returned if
+    /// it resolves back to `address`, as anyone can claim any reverse name.
+    pub fn reverse_resolve(&self, address: H160) {
+        let registry = self.ens_registry;
+        self.spawn_read(
+            WalletOp::Ens,
+            self.ens_name_tx.clone(),
+            move |web3| async move {
+                let hex: String = address.as_bytes().to_hex();
+                let node = namehash(&format!("{}.addr.reverse", hex));
+                let Some(resolver) = resolver(&web3, registry, node).await? else {
+                    return Ok(Some((address, None)));
+                };
+                let data = call(&web3, resolver, node_call("name", node)).await?;
+                let Some(name) = data.as_deref().and_then(decode_name) else {
+                    return Ok(Some((address, None)));
+                };
+                let verified = resolve(&web3, registry, &name).await? == Some(address);
+                Ok(Some((address, verified.then_some(name))))
+            },
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use serde_json::json;
+use web3::{
+    ethabi::Token,
+    types::{CallRequest, H160, H256, H520, U256},
+    Transport,
+};
+
+use crate::{
+    multicall, Contract, ContractError, Eip712Domain, EthWallet, RequestId, TypedData, WalletOp,
+    Web3Error,
+};
+
+const ERC20_ABI: &[u8] = include_bytes!("abi/erc20.json");
+
+/// Token info from `Erc20::metadata`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenMetadata {
+    pub address: H160,
+    /// Empty if the token does not implement the optional `name`.
+    pub name: String,
+    /// Empty if the token does not implement the optional `symbol`.
+    pub symbol: String,
+    pub decimals: u8,
+    pub total_supply: U256,
+}
+
+/// An EIP-2612 permit from `Erc20::sign_permit`, submit it with
+/// `Erc20::encode_permit`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermitSignature {
+    pub token: H160,
+    pub owner: H160,
+    pub spender: H160,
+    pub value: U256,
+    pub nonce: U256,
+    pub deadline: U256,
+    pub v: u8,
+    pub r: H256,
+    pub s: H256,
+}
+
+/// The fields of `TokenMetadata` that never change.
+#[derive(Clone, Debug)]
+pub(crate) struct TokenInfo {
+    name: String,
+    symbol: String,
+    decimals: u8,
+}
+
+/// An ERC-20 token. The reads are delivered through `EthWallet::recv_call`
+/// under the method name, decode them with `decode_uint` or `decode_string`.
+pub struct Erc20 {
+    pub contract: Contract,
+}
+
+impl Erc20 {
+    pub fn new(address: H160) -> Self {
+        Erc20 {
+            contract: Contract::embedded(address, ERC20_ABI),
+        }
+    }
+
+    pub fn balance_of(&self, wallet: &EthWallet, owner: H160) -> RequestId {
+        self.contract
+            .call_known(wallet, "balanceOf", &[Token::Address(owner)])
+    }
+
+    pub fn decimals(&self, wallet: &EthWallet) -> RequestId {
+        self.contract.call_known(wallet, "decimals", &[])
+    }
+
+    pub fn symbol(&self, wallet: &EthWallet) -> RequestId {
+        self.contract.call_known(wallet, "symbol", &[])
+    }
+
+    /// Fetch the name, symbol, decimals and total supply in one multicall,
+    /// delivered through `EthWallet::recv_token_metadata` and
+    /// `TokenMetadataFetched`. Only the total supply is fetched again for a
+    /// token already queried on the current chain.
+    pub fn metadata(&self, wallet: &EthWallet) {
+        let address = self.contract.address;
+        // the same address may be another token on another chain
+        let key = (wallet.chain_id, address);
+        let cached = wallet.token_cache.lock().unwrap().get(&key).cloned();
+        let methods: &[&str] = match cached {
+            Some(_) => &["totalSupply"],
+            None => &["name", "symbol", "decimals", "totalSupply"],
+        };
+        let calls = methods
+            .iter()
+            .map(|method| (address, self.contract.encode_known(method, &[])))
+            .collect();
+        let call = CallRequest {
+            to: Some(wallet.multicall_address),
+            data: Some(multicall::encode(calls).into()),
+            ..Default::default()
+        };
+
+        let contract = self.contract.clone();
+        let cache = wallet.token_cache.clone();
+        wallet.spawn_read(
+            WalletOp::Multicall,
+            wallet.token_metadata_tx.clone(),
+            move |web3| {
+                let (call, contract) = (call.clone(), contract.clone());
+                let (cache, cached) = (cache.clone(), cached.clone());
+                async move {
+                    let data = web3.eth().call(call, None).await?;
+                    let mut results = multicall::decode(&data.0)?
+                        .into_iter()
+                        .map(|(success, data)| if success { data } else { vec![] });
+                    let info = match cached {
+                        Some(info) => info,
+                        None => {
+                            let name =
+                                decode_text(&contract, "name", &results.next().unwrap_or_default());
+                            let symbol = decode_text(
+                                &contract,
+                                "symbol",
+                                &results.next().unwrap_or_default(),
+                            );
+                            let decimals: U256 = contract
+                                .decode_one("decimals", &results.next().unwrap_or_default())?;
+                            let info = TokenInfo {
+                                name,
+                                symbol,
+                                decimals: decimals.low_u32() as u8,
+                            };
+                            cache.lock().unwrap().insert(key, info.clone());
+                            info
+                        }
+                    };
+                    let total_supply =
+                        contract.decode_one("totalSupply", &results.next().unwrap_or_default())?;
+                    Ok(Some(TokenMetadata {
+                        address: contract.address,
+                        name: info.name,
+                        symbol: info.symbol,
+                        decimals: info.decimals,
+                        total_supply,
+                    }))
+                }
+            },
+        );
+    }
+
+    pub fn allowance(&self, wallet: &EthWallet, owner: H160, spender: H160) -> RequestId {
+        self.contract.call_known(
+            wallet,
+            "allowance",
+            &[Token::Address(owner), Token::Address(spender)],
+        )
+    }
+
+    /// Call data for `transfer`, send it to the token with `EthWallet::send_addr`.
+    pub fn encode_transfer(&self, to: H160, amount: U256) -> Vec<u8> {
+        self.contract
+            .encode_known("transfer", &[Token::Address(to), Token::Uint(amount)])
+    }
+
+    /// Call data for `approve`, send it to the token with `EthWallet::send_addr`.
+    pub fn encode_approve(&self, spender: H160, amount: U256) -> Vec<u8> {
+        self.contract
+            .encode_known("approve", &[Token::Address(spender), Token::Uint(amount)])
+    }
+
+    /// Sign an EIP-2612 `permit` letting `spender` use `value` of `owner`'s
+    /// tokens until `deadline` (a unix timestamp), without a transaction from
+    /// `owner`. The token's name, version and nonce and the wallet's chain are
+    /// read first, the signature is delivered through `EthWallet::recv_permit`
+    /// and `PermitSigned`.
+    pub fn sign_permit(
+        &self,
+        wallet: &EthWallet,
+        owner: H160,
+        spender: H160,
+        value: U256,
+        deadline: U256,
+    ) {
+        let token = self.contract.address;
+        let calls = [
+            self.contract.encode_known("name", &[]),
+            self.contract.encode_known("version", &[]),
+            self.contract
+                .encode_known("nonces", &[Token::Address(owner)]),
+            self.contract.encode_known("DOMAIN_SEPARATOR", &[]),
+        ];
+        let call = CallRequest {
+            to: Some(wallet.multicall_address),
+            data: Some(multicall::encode(calls.map(|data| (token, data)).to_vec()).into()),
+            ..Default::default()
+        };
+
+        let contract = self.contract.clone();
+        let read = wallet.read_transport();
+        let transport = wallet.transport.clone();
+        let tx = wallet.permit_tx.clone();
+        wallet.spawn(WalletOp::Permit, tx, move |transports| async move {
+            // `EthWallet::chain_id` is unknown until the connect result is read
+            let web3 = transports.web3(&transport)?;
+            let chain_id = web3.eth().chain_id().await?.as_u64();
+            let data = transports.web3(&read)?.eth().call(call, None).await?;
+            let mut results = multicall::decode(&data.0)?
+                .into_iter()
+                .map(|(success, data)| if success { data } else { vec![] });
+            let mut next = || results.next().unwrap_or_default();
+            let name: String = contract.decode_one("name", &next())?;
+            let version: Option<String> = contract.decode_one("version", &next()).ok();
+            let nonce: U256 = contract.decode_one("nonces", &next())?;
+            let separator: Option<H256> = contract.decode_one("DOMAIN_SEPARATOR", &next()).ok();
+            let domain =
+                permit_domain(token, chain_id, name, version, separator).ok_or_else(|| {
+                    Web3Error::InvalidParams("token DOMAIN_SEPARATOR has an unknown domain".into())
+                })?;
+
+            let message = json!({
+                "owner": owner,
+                "spender": spender,
+                "value": value.to_string(),
+                "nonce": nonce.to_string(),
+                "deadline": deadline.to_string(),
+            });
+            let typed_data = TypedData::new("Permit", domain, message).with_type(
+                "Permit",
+                &[
+                    ("owner", "address"),
+                    ("spender", "address"),
+                    ("value", "uint256"),
+                    ("nonce", "uint256"),
+                    ("deadline", "uint256"),
+                ],
+            );
+            let params = vec![json!(owner), json!(typed_data.to_json()?)];
+            let signature = web3
+                .transport()
+                .execute("eth_signTypedData_v4", params)
+                .await?;
+            let signature: H520 = serde_json::from_value(signature)?;
+            let v = signature[64];
+            Ok(Some(PermitSignature {
+                token,
+                owner,
+                spender,
+                value,
+                nonce,
+                deadline,
+                // some signers return the recovery id instead of 27/28
+                v: if v < 27 { v + 27 } else { v },
+                r: H256::from_slice(&signature[..32]),
+                s: H256::from_slice(&signature[32..64]),
+            }))
+        });
+    }
+
+    /// Call data for `permit`, anyone can send it to the token, e.g. a relayer.
+    pub fn encode_permit(&self, permit: &PermitSignature) -> Vec<u8> {
+        self.contract.encode_known(
+            "permit",
+            &[
+                Token::Address(permit.owner),
+                Token::Address(permit.spender),
+                Token::Uint(permit.value),
+                Token::Uint(permit.deadline),
+                Token::Uint(permit.v.into()),
+                Token::FixedBytes(permit.r.as_bytes().to_vec()),
+                Token::FixedBytes(permit.s.as_bytes().to_vec()),
+            ],
+        )
+    }
+
+    /// The result of `balanceOf`, `decimals`, `allowance` or `totalSupply`.
+    pub fn decode_uint(&self, method: &str, data: &[u8]) -> Result<U256, ContractError> {
+        self.contract.decode_one(method, data)
+    }
+
+    /// The result of `symbol` or `name`.
+    pub fn decode_string(&self, method: &str, data: &[u8]) -> Result<String, ContractError> {
+        self.contract.decode_one(method, data)
+    }
+}
+
+/// A `string` result, or the `bytes32` some early tokens (e.g. MKR) return
+/// instead. Empty if the call failed.
+fn decode_text(contract: &Contract, method: &str, data: &[u8]) -> String {
+    if data.len() == 32 {
+        let end = data.iter().position(|b| *b == 0).unwrap_or(32);
+        return String::from_utf8_lossy(&data[..end]).into_owned();
+    }
+    contract.decode_one(method, data).unwrap_or_default()
+}
+
+/// The permit domain of `token`: the `version()` it reports, else `"1"`, else
+/// no version at all (e.g. some bridged tokens), whichever matches its
+/// `DOMAIN_SEPARATOR`. Without one, trust the reported version.
+fn permit_domain(
+    token: H160,
+    chain_id: u64,
+    name: String,
+    version: Option<String>,
+    separator: Option<H256>,
+) -> Option<Eip712Domain> {
+    let mut domains = version
+        .map(Some)
+        .into_iter()
+        .chain([Some("1".to_owned()), None])
+        .map(|version| Eip712Domain {
+            name: Some(name.clone()),
+            version,
+            chain_id: Some(chain_id),
+            verifying_contract: Some(token),
+            salt: None,
+        });
+    match separator {
+        Some(separator) => domains.find(|domain| domain.separator() == separator),
+        None => domains.next(),
+    }
+}
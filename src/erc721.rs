@@ -0,0 +1,87 @@
+use web3::{
+    ethabi::Token,
+    types::{Log, H160, U256},
+};
+
+use crate::{Contract, ContractError, EthWallet, RequestId};
+
+const ERC721_ABI: &[u8] = include_bytes!("abi/erc721.json");
+
+/// An ERC-721 collection. The reads are delivered through `EthWallet::recv_call`
+/// under the method name, decode them with the `decode_*` helpers.
+pub struct Erc721 {
+    pub contract: Contract,
+}
+
+/// A decoded `Transfer` event, `from` is zero for a mint and `to` for a burn.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NftTransfer {
+    pub from: H160,
+    pub to: H160,
+    pub token_id: U256,
+}
+
+impl Erc721 {
+    pub fn new(address: H160) -> Self {
+        Erc721 {
+            contract: Contract::embedded(address, ERC721_ABI),
+        }
+    }
+
+    pub fn owner_of(&self, wallet: &EthWallet, token_id: U256) -> RequestId {
+        self.contract
+            .call_known(wallet, "ownerOf", &[Token::Uint(token_id)])
+    }
+
+    pub fn balance_of(&self, wallet: &EthWallet, owner: H160) -> RequestId {
+        self.contract
+            .call_known(wallet, "balanceOf", &[Token::Address(owner)])
+    }
+
+    pub fn token_uri(&self, wallet: &EthWallet, token_id: U256) -> RequestId {
+        self.contract
+            .call_known(wallet, "tokenURI", &[Token::Uint(token_id)])
+    }
+
+    /// Call data for `transferFrom`, send it to the collection with `EthWallet::send_addr`.
+    pub fn encode_transfer_from(&self, from: H160, to: H160, token_id: U256) -> Vec<u8> {
+        self.contract.encode_known(
+            "transferFrom",
+            &[
+                Token::Address(from),
+                Token::Address(to),
+                Token::Uint(token_id),
+            ],
+        )
+    }
+
+    /// The result of `ownerOf` or `getApproved`.
+    pub fn decode_address(&self, method: &str, data: &[u8]) -> Result<H160, ContractError> {
+        self.contract.decode_one(method, data)
+    }
+
+    /// The result of `balanceOf`.
+    pub fn decode_uint(&self, method: &str, data: &[u8]) -> Result<U256, ContractError> {
+        self.contract.decode_one(method, data)
+    }
+
+    /// The result of `tokenURI`, `name` or `symbol`.
+    pub fn decode_string(&self, method: &str, data: &[u8]) -> Result<String, ContractError> {
+        self.contract.decode_one(method, data)
+    }
+
+    pub fn decode_transfer(&self, log: &Log) -> Result<NftTransfer, ContractError> {
+        let tokens = self.contract.decode_event("Transfer", log)?;
+        match tokens.as_slice() {
+            [Token::Address(from), Token::Address(to), Token::Uint(token_id)] => Ok(NftTransfer {
+                from: *from,
+                to: *to,
+                token_id: *token_id,
+            }),
+            _ => Err(ContractError::DecodeLog {
+                event: "Transfer".to_owned(),
+                source: web3::ethabi::Error::InvalidData,
+            }),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use web3::{
+    ethabi::Token,
+    types::{H160, H256, U256},
+};
+
+/// Conversion of a decoded abi value into a Rust type, see `Contract::decode_one`.
+pub trait FromToken: Sized {
+    fn from_token(token: Token) -> Option<Self>;
+}
+
+impl FromToken for Token {
+    fn from_token(token: Token) -> Option<Self> {
+        Some(token)
+    }
+}
+
+impl FromToken for U256 {
+    fn from_token(token: Token) -> Option<Self> {
+        token.into_uint()
+    }
+}
+
+impl FromToken for H160 {
+    fn from_token(token: Token) -> Option<Self> {
+        token.into_address()
+    }
+}
+
+impl FromToken for H256 {
+    fn from_token(token: Token) -> Option<Self> {
+        match token {
+            Token::FixedBytes(bytes) if bytes.len() == 32 => Some(H256::from_slice(&bytes)),
+            _ => None,
+        }
+    }
+}
+
+impl FromToken for bool {
+    fn from_token(token: Token) -> Option<Self> {
+        token.into_bool()
+    }
+}
+
+impl FromToken for String {
+    fn from_token(token: Token) -> Option<Self> {
+        token.into_string()
+    }
+}
+
+impl FromToken for Vec<u8> {
+    fn from_token(token: Token) -> Option<Self> {
+        match token {
+            Token::Bytes(bytes) | Token::FixedBytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: FromToken),+> FromToken for ($($name,)+) {
+            fn from_token(token: Token) -> Option<Self> {
+                let mut tokens = token.into_tuple()?.into_iter();
+                let value = ($($name::from_token(tokens.next()?)?,)+);
+                tokens.next().is_none().then_some(value)
+            }
+        }
+    };
+}
+
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
@@ -0,0 +1,196 @@
+use serde_json::{json, Value};
+use web3::ethabi::{param_type::Reader, ParamType};
+
+use crate::ContractError;
+
+/// Convert human-readable signatures, e.g.
+/// `function balanceOf(address owner) view returns (uint256)`, to a JSON ABI.
+pub(crate) fn to_json(signatures: &[&str]) -> Result<Vec<u8>, ContractError> {
+    let entries = signatures
+        .iter()
+        .map(|signature| {
+            entry(signature.trim())
+                .ok_or_else(|| ContractError::BadSignature(signature.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::Array(entries).to_string().into_bytes())
+}
+
+fn entry(signature: &str) -> Option<Value> {
+    let (kind, rest) = match signature.split_once(char::is_whitespace) {
+        Some((kind @ ("function" | "event" | "error"), rest)) => (kind, rest.trim_start()),
+        _ if signature.starts_with("constructor") => ("constructor", signature),
+        _ => ("function", signature),
+    };
+    let open = rest.find('(')?;
+    let name = rest[..open].trim();
+    let (inputs, rest) = balanced(&rest[open..])?;
+    let inputs = params(inputs, kind == "event")?;
+
+    let mut modifiers = rest.split_whitespace();
+    let mut mutability = "nonpayable";
+    let mut anonymous = false;
+    for modifier in modifiers.by_ref() {
+        match modifier {
+            "view" | "pure" | "payable" | "nonpayable" => mutability = modifier,
+            "anonymous" => anonymous = true,
+            "returns" => break,
+            "external" | "public" => {}
+            _ => return None,
+        }
+    }
+    let outputs = match rest.find("returns") {
+        Some(at) => {
+            let (outputs, tail) = balanced(rest[at + "returns".len()..].trim_start())?;
+            if !tail.trim().is_empty() {
+                return None;
+            }
+            params(outputs, false)?
+        }
+        None => vec![],
+    };
+
+    Some(match kind {
+        "constructor" => json!({
+            "type": "constructor",
+            "inputs": inputs,
+            "stateMutability": mutability,
+        }),
+        "event" => json!({
+            "type": "event",
+            "name": name,
+            "inputs": inputs,
+            "anonymous": anonymous,
+        }),
+        "error" => json!({ "type": "error", "name": name, "inputs": inputs }),
+        _ => json!({
+            "type": "function",
+            "name": name,
+            "inputs": inputs,
+            "outputs": outputs,
+            "stateMutability": mutability,
+        }),
+    })
+}
+
+/// Split `(...)rest` into the text inside the parentheses and `rest`.
+fn balanced(s: &str) -> Option<(&str, &str)> {
+    if !s.starts_with('(') {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&s[1..i], &s[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split on the commas outside of nested tuples.
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn params(list: &str, event: bool) -> Option<Vec<Value>> {
+    if list.trim().is_empty() {
+        return Some(vec![]);
+    }
+    split_top_level(list)
+        .into_iter()
+        .map(|param| {
+            let (kind, rest) = param_type(param.trim())?;
+            let mut indexed = false;
+            let mut name = "";
+            for word in rest.split_whitespace() {
+                match word {
+                    "indexed" if event => indexed = true,
+                    "memory" | "calldata" | "storage" | "payable" => {}
+                    _ if name.is_empty() => name = word,
+                    _ => return None,
+                }
+            }
+            let mut value = param_json(name, &kind);
+            if event {
+                value["indexed"] = json!(indexed);
+            }
+            Some(value)
+        })
+        .collect()
+}
+
+/// Read the type at the start of `s`, returning it and the rest.
+fn param_type(s: &str) -> Option<(ParamType, &str)> {
+    let s = s.strip_prefix("tuple").unwrap_or(s);
+    if s.starts_with('(') {
+        let (inner, mut rest) = balanced(s)?;
+        let mut kind = ParamType::Tuple(if inner.trim().is_empty() {
+            vec![]
+        } else {
+            split_top_level(inner)
+                .into_iter()
+                .map(|p| param_type(p.trim()).map(|(kind, _)| kind))
+                .collect::<Option<_>>()?
+        });
+        while let Some(array) = rest.strip_prefix('[') {
+            let end = array.find(']')?;
+            kind = match array[..end].trim() {
+                "" => ParamType::Array(Box::new(kind)),
+                len => ParamType::FixedArray(Box::new(kind), len.parse().ok()?),
+            };
+            rest = &array[end + 1..];
+        }
+        return Some((kind, rest));
+    }
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    // unknown names are enums, read as uint8 like ethabi does
+    Some((Reader::read(&s[..end]).ok()?, &s[end..]))
+}
+
+/// A JSON ABI param, spelling tuples out as `components`.
+fn param_json(name: &str, kind: &ParamType) -> Value {
+    let mut suffix = String::new();
+    let mut inner = kind;
+    loop {
+        match inner {
+            ParamType::Array(k) => {
+                suffix.insert_str(0, "[]");
+                inner = k;
+            }
+            ParamType::FixedArray(k, len) => {
+                suffix.insert_str(0, &format!("[{}]", len));
+                inner = k;
+            }
+            _ => break,
+        }
+    }
+    match inner {
+        ParamType::Tuple(components) => json!({
+            "name": name,
+            "type": format!("tuple{}", suffix),
+            "components": components.iter().map(|k| param_json("", k)).collect::<Vec<_>>(),
+        }),
+        _ => json!({ "name": name, "type": kind.to_string() }),
+    }
+}
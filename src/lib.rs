@@ -1,23 +1,102 @@
 use async_channel::{unbounded, Receiver, Sender, TryRecvError};
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     tasks::{IoTaskPool, TaskPool},
 };
 use chamomile_types::PeerId;
+use futures_timer::Delay;
+use rustc_hex::{FromHex, FromHexError, ToHex};
+use serde::{Serialize, Serializer};
+use serde_json::json;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    pin::pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 use web3::{
-    ethabi::Contract as EthContract,
-    transports::eip_1193,
-    types::{CallRequest, TransactionRequest},
+    ethabi::{
+        self, Contract as EthContract, Error as EthError, Event as EthEvent, Function, ParamType,
+        RawLog,
+    },
+    futures::{
+        future::{select, Either, LocalBoxFuture},
+        StreamExt,
+    },
+    types::{Bytes, CallRequest, FilterBuilder, TransactionId, TransactionRequest},
+    Transport, Web3,
 };
 
+#[cfg(feature = "http")]
+use web3::signing::{Key, SecretKeyRef};
+
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::tasks::Task;
+
+use transport::Transports;
+
+mod eip6963;
+mod ens;
+mod erc20;
+mod erc721;
+mod from_token;
+mod human_abi;
+#[cfg(any(test, feature = "testing"))]
+mod mock;
+mod multicall;
+#[cfg(feature = "signing")]
+mod signature;
+mod siwe;
+#[cfg(test)]
+mod tests;
+mod transport;
+mod typed_data;
+#[cfg(feature = "walletconnect")]
+mod walletconnect;
+#[cfg(feature = "ws")]
+mod ws;
+
+pub use eip6963::{ProviderAnnounced, ProviderInfo, WalletProviders};
+pub use ens::{namehash, ENS_REGISTRY};
+pub use erc20::{Erc20, PermitSignature, TokenMetadata};
+pub use erc721::{Erc721, NftTransfer};
+pub use from_token::FromToken;
+#[cfg(any(test, feature = "testing"))]
+pub use mock::MockTransport;
+pub use multicall::MULTICALL3;
+#[cfg(feature = "signing")]
+pub use signature::{recover_personal_signer, recover_signer, SignatureError};
+#[cfg(feature = "signing")]
+pub use siwe::VerifyOpts;
+pub use siwe::{SiweError, SiweMessage};
+pub use transport::{CustomTransport, TransportConfig, WalletTransport, Web3Transport};
+pub use typed_data::{Eip712Domain, TypedData, TypedDataField};
+#[cfg(feature = "walletconnect")]
+pub use walletconnect::{
+    WalletConnect, WalletConnectPlugin, WalletConnectState, WalletConnectStateChanged,
+};
 pub use web3::{
     ethabi::Token,
-    types::{H160, H256, H520, U256},
+    types::{
+        Block, BlockId, BlockNumber, FeeHistory, Filter, Log, Transaction, TransactionReceipt,
+        H160, H256, H520, U256,
+    },
 };
+#[cfg(feature = "ws")]
+pub use ws::{NewHead, WsConnected, WsConnection, WsEndpoint, WsWalletPlugin};
 
+#[derive(Debug, PartialEq, Eq)]
 pub enum RecvError {
     Empty,
     Closed,
+    /// The wallet reported no accounts, e.g. it is locked or permission was revoked.
+    NoAccounts,
 }
 
 impl From<TryRecvError> for RecvError {
@@ -31,179 +110,4027 @@ impl From<TryRecvError> for RecvError {
 
 pub struct WalletPlugin;
 
+impl WalletPlugin {
+    /// Start from the default settings and adjust them with the `WalletConfig`
+    /// setters, then add the result as the plugin.
+    pub fn builder() -> WalletConfig {
+        WalletConfig::default()
+    }
+
+    pub fn with_task_pool_policy(policy: TaskPoolPolicy) -> WalletConfig {
+        WalletConfig {
+            task_pool_policy: policy,
+            ..Default::default()
+        }
+    }
+
+    /// Use a JSON-RPC node at `url` instead of the injected wallet.
+    #[cfg(feature = "http")]
+    pub fn http(url: &str) -> WalletConfig {
+        WalletConfig {
+            transport: TransportConfig::Http {
+                url: url.to_owned(),
+                local_key: None,
+            },
+            ..Default::default()
+        }
+    }
+}
+
 impl Plugin for WalletPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, init_eth_wallet);
+        WalletConfig::default().build(app);
     }
 }
 
-#[derive(Resource)]
-pub struct EthWallet {
-    pub accounts: Vec<H160>,
+/// What to do when Bevy has not set up the `IoTaskPool` (normally done by `TaskPoolPlugin`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TaskPoolPolicy {
+    /// Panic at startup. Without `WalletPlugin`, requests fail with
+    /// `Web3Error::TaskPoolUnavailable` instead.
+    RequireExisting,
+    /// Log a warning and create a default pool.
+    #[default]
+    CreateDefault,
+}
+
+/// `WalletPlugin` with custom settings, also available as a resource.
+#[derive(Resource, Clone, Debug)]
+pub struct WalletConfig {
+    pub task_pool_policy: TaskPoolPolicy,
+    pub transport: TransportConfig,
+    /// Call `EthWallet::silent_connect` at startup, restoring the session of a
+    /// wallet that already authorized the site.
+    pub auto_connect: bool,
+    /// Switch to this chain once connected, if the wallet is on another one.
+    pub default_chain: Option<u64>,
+    /// Poll interval of `EthWallet::wait_for_receipt`.
+    pub receipt_poll_interval: Duration,
+    /// Multicall3 deployment used by `EthWallet::batch_call`.
+    pub multicall_address: H160,
+    /// ENS registry used by `EthWallet::resolve_ens` and `reverse_resolve`.
+    pub ens_registry: H160,
+    pub operation_policy: OperationPolicy,
+    /// Drain the results every frame into Bevy events (`AccountConnected`,
+    /// `MessageSigned`, `TransactionSent`, `CallReturned`, ...). Off by
+    /// default: the results then wait for the `EthWallet::recv_*` methods or
+    /// `poll`. A result goes to the events or to `recv_*`, never both.
+    pub emit_events: bool,
+}
+
+impl Default for WalletConfig {
+    fn default() -> Self {
+        WalletConfig {
+            task_pool_policy: TaskPoolPolicy::default(),
+            transport: TransportConfig::default(),
+            auto_connect: false,
+            default_chain: None,
+            receipt_poll_interval: WatchOptions::default().interval,
+            multicall_address: MULTICALL3,
+            ens_registry: ENS_REGISTRY,
+            operation_policy: OperationPolicy::default(),
+            emit_events: false,
+        }
+    }
+}
+
+impl WalletConfig {
+    pub fn task_pool_policy(mut self, policy: TaskPoolPolicy) -> Self {
+        self.task_pool_policy = policy;
+        self
+    }
+
+    pub fn transport(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn auto_connect(mut self, auto_connect: bool) -> Self {
+        self.auto_connect = auto_connect;
+        self
+    }
+
+    pub fn default_chain(mut self, chain_id: u64) -> Self {
+        self.default_chain = Some(chain_id);
+        self
+    }
+
+    pub fn receipt_poll_interval(mut self, interval: Duration) -> Self {
+        self.receipt_poll_interval = interval;
+        self
+    }
+
+    pub fn multicall_address(mut self, address: H160) -> Self {
+        self.multicall_address = address;
+        self
+    }
+
+    pub fn ens_registry(mut self, address: H160) -> Self {
+        self.ens_registry = address;
+        self
+    }
+
+    pub fn operation_policy(mut self, policy: OperationPolicy) -> Self {
+        self.operation_policy = policy;
+        self
+    }
+
+    pub fn emit_events(mut self, emit_events: bool) -> Self {
+        self.emit_events = emit_events;
+        self
+    }
+
+    /// Sign with `key` instead of the node's accounts, only for the http transport.
+    #[cfg(feature = "http")]
+    pub fn with_local_key(mut self, key: web3::signing::SecretKey) -> Self {
+        if let TransportConfig::Http { local_key, .. } = &mut self.transport {
+            *local_key = Some(key);
+        }
+        self
+    }
+}
+
+impl Plugin for WalletConfig {
+    fn build(&self, app: &mut App) {
+        #[cfg(feature = "http")]
+        app.init_resource::<RpcConfig>().add_systems(
+            PreUpdate,
+            sync_rpc_config.run_if(resource_exists::<EthWallet>),
+        );
+
+        app.insert_resource(self.clone())
+            .init_resource::<WalletProviders>()
+            .add_event::<ProviderAnnounced>()
+            .add_event::<AccountConnected>()
+            .add_event::<MessageSigned>()
+            .add_event::<TransactionSent>()
+            .add_event::<CallReturned>()
+            .add_event::<ChainSwitched>()
+            .add_event::<ChainAdded>()
+            .add_event::<BalanceFetched>()
+            .add_event::<TransactionMined>()
+            .add_event::<PersonalMessageSigned>()
+            .add_event::<TypedDataSigned>()
+            .add_event::<GasEstimated>()
+            .add_event::<LogReceived>()
+            .add_event::<AccountsChanged>()
+            .add_event::<ChainChanged>()
+            .add_event::<WalletDisconnected>()
+            .add_event::<AssetWatched>()
+            .add_event::<MulticallReturned>()
+            .add_event::<PermissionsChanged>()
+            .add_event::<NonceFetched>()
+            .add_event::<BlockNumberFetched>()
+            .add_event::<NewBlock>()
+            .add_event::<TransactionFetched>()
+            .add_event::<ReceiptFetched>()
+            .add_event::<GasPriceFetched>()
+            .add_event::<FeeHistoryFetched>()
+            .add_event::<BlockFetched>()
+            .add_event::<TransactionConfirmed>()
+            .add_event::<CodeFetched>()
+            .add_event::<TransactionReplaced>()
+            .add_event::<StorageFetched>()
+            .add_event::<EnsResolved>()
+            .add_event::<EnsReverseResolved>()
+            .add_event::<LogsFetched>()
+            .add_event::<ProviderMissing>()
+            .add_event::<ContractCallReturned>()
+            .add_event::<ConfirmedTransaction>()
+            .add_event::<FeesSuggested>()
+            .add_event::<TokenMetadataFetched>()
+            .add_event::<PermitSigned>()
+            .add_event::<OperationFailed>()
+            .insert_non_send_resource(WalletTasks::new(self.task_pool_policy))
+            .add_systems(Startup, (init_eth_wallet, eip6963::discover_providers))
+            .add_systems(PostStartup, (detect_provider, auto_connect))
+            .add_systems(
+                PreUpdate,
+                (
+                    eip6963::recv_providers,
+                    (
+                        emit_wallet_events.run_if(events_enabled),
+                        switch_to_default_chain,
+                    )
+                        .chain()
+                        .run_if(resource_exists::<EthWallet>),
+                ),
+            )
+            .add_systems(
+                PostUpdate,
+                run_wallet_tasks.run_if(resource_exists::<EthWallet>),
+            );
+    }
+}
+
+/// Fired when `EthWallet::connect` resolves. Like the other result events,
+/// only with `WalletConfig::emit_events`.
+///
+/// ```no_run
+/// use bevy::prelude::*;
+/// use bevy_web3::{AccountConnected, WalletPlugin};
+///
+/// App::new()
+///     .add_plugins(WalletPlugin::builder().emit_events(true))
+///     .add_systems(Update, on_connected);
+///
+/// fn on_connected(mut events: EventReader<AccountConnected>) {
+///     for event in events.read() {
+///         info!("connected {:?} on chain {}", event.address, event.chain_id);
+///     }
+/// }
+/// ```
+#[derive(Event, Clone, Debug)]
+pub struct AccountConnected {
+    pub address: H160,
     pub chain_id: u64,
-    account_tx: Sender<(Vec<H160>, u64)>,
-    account_rx: Receiver<(Vec<H160>, u64)>,
-    signature_tx: Sender<H520>,
-    signature_rx: Receiver<H520>,
-    transaction_tx: Sender<H256>,
-    transaction_rx: Receiver<H256>,
-    call_tx: Sender<(String, Vec<u8>)>,
-    call_rx: Receiver<(String, Vec<u8>)>,
-}
-
-fn init_eth_wallet(mut commands: Commands) {
-    let (account_tx, account_rx) = unbounded();
-    let (signature_tx, signature_rx) = unbounded();
-    let (transaction_tx, transaction_rx) = unbounded();
-    let (call_tx, call_rx) = unbounded();
-
-    commands.insert_resource(EthWallet {
-        accounts: vec![],
-        chain_id: 0,
-        account_tx,
-        account_rx,
-        signature_tx,
-        signature_rx,
-        transaction_tx,
-        transaction_rx,
-        call_tx,
-        call_rx,
-    });
 }
 
-impl EthWallet {
-    pub fn connect(&self) {
-        let tx = self.account_tx.clone();
-        IoTaskPool::get_or_init(TaskPool::new)
-            .spawn(async move {
-                let provider = eip_1193::Provider::default().unwrap().unwrap();
-                let transport = eip_1193::Eip1193::new(provider);
-                let web3 = web3::Web3::new(transport);
+/// The result of `EthWallet::sign`, `id` being the one it returned.
+#[derive(Event, Clone, Debug)]
+pub struct MessageSigned {
+    pub id: RequestId,
+    pub signature: H520,
+}
+
+/// The transaction was submitted, not mined yet: poll its status with
+/// `EthWallet::get_receipt` or wait for it with `watch_transaction`.
+#[derive(Event, Clone, Debug)]
+pub struct TransactionSent {
+    pub id: RequestId,
+    pub hash: H256,
+}
 
-                let addrs = web3.eth().request_accounts().await.unwrap();
-                let chain = web3.eth().chain_id().await.unwrap();
+#[derive(Event, Clone, Debug)]
+pub struct CallReturned {
+    pub id: RequestId,
+    pub method: String,
+    pub data: Vec<u8>,
+}
 
-                if !addrs.is_empty() {
-                    let _ = tx.send((addrs, chain.as_u64())).await;
-                }
-            })
-            .detach();
+#[derive(Event, Clone, Debug)]
+pub struct ChainSwitched(pub u64);
+
+#[derive(Event, Clone, Debug)]
+pub struct ChainAdded(pub u64);
+
+#[derive(Event, Clone, Debug)]
+pub struct BalanceFetched(pub U256);
+
+#[derive(Event, Clone, Debug)]
+pub struct TransactionMined(pub TxReceipt);
+
+#[derive(Event, Clone, Debug)]
+pub struct PersonalMessageSigned {
+    pub id: RequestId,
+    pub signature: H520,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct TypedDataSigned {
+    pub id: RequestId,
+    pub signature: H520,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct GasEstimated(pub U256);
+
+#[derive(Event, Clone, Debug)]
+pub struct LogReceived(pub Log);
+
+/// The wallet switched accounts, `EthWallet::accounts` is already updated.
+/// Empty when the wallet was locked or the permission revoked.
+#[derive(Event, Clone, Debug)]
+pub struct AccountsChanged(pub Vec<H160>);
+
+/// The wallet switched networks, `EthWallet::chain_id` is already updated.
+#[derive(Event, Clone, Debug)]
+pub struct ChainChanged(pub u64);
+
+#[derive(Event, Clone, Debug)]
+pub struct WalletDisconnected;
+
+/// Whether the user added the asset from `EthWallet::watch_asset`.
+#[derive(Event, Clone, Debug)]
+pub struct AssetWatched(pub bool);
+
+/// The `(success, return data)` of each call of `EthWallet::multicall`.
+#[derive(Event, Clone, Debug)]
+pub struct MulticallReturned(pub Vec<(bool, Vec<u8>)>);
+
+/// The permissions granted to the game, e.g. `eth_accounts`.
+#[derive(Event, Clone, Debug)]
+pub struct PermissionsChanged(pub Vec<String>);
+
+#[derive(Event, Clone, Debug)]
+pub struct NonceFetched(pub U256);
+
+#[derive(Event, Clone, Debug)]
+pub struct BlockNumberFetched(pub U256);
+
+/// A block seen by `EthWallet::watch_blocks`, each block is reported once.
+#[derive(Event, Clone, Debug)]
+pub struct NewBlock {
+    pub number: u64,
+    pub hash: H256,
+    /// Unix time in seconds.
+    pub timestamp: u64,
+}
+
+/// `None` if the node doesn't know the transaction.
+#[derive(Event, Clone, Debug)]
+pub struct TransactionFetched(pub Option<Transaction>);
+
+/// `None` while the transaction is pending.
+#[derive(Event, Clone, Debug)]
+pub struct ReceiptFetched(pub Option<TransactionReceipt>);
+
+#[derive(Event, Clone, Debug)]
+pub struct GasPriceFetched(pub U256);
+
+#[derive(Event, Clone, Debug)]
+pub struct FeeHistoryFetched(pub FeeHistory);
+
+#[derive(Event, Clone, Debug)]
+pub struct BlockFetched(pub Option<Block<H256>>);
+
+/// The transaction of a `watch_transaction` with `WatchOptions::confirmations`
+/// is buried under that many blocks.
+#[derive(Event, Clone, Debug)]
+pub struct TransactionConfirmed(pub TxReceipt);
+
+/// The code deployed at an address, empty for an externally owned account.
+#[derive(Event, Clone, Debug)]
+pub struct CodeFetched {
+    pub address: H160,
+    pub code: Vec<u8>,
+}
+
+/// Which of a transaction and its `replace_transaction`/`cancel_transaction`
+/// replacement was mined.
+#[derive(Event, Clone, Debug)]
+pub struct TransactionReplaced {
+    pub nonce: U256,
+    pub hash: H256,
+    /// `true` if the replacement was mined, `false` if the original was.
+    pub replaced: bool,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct StorageFetched {
+    pub address: H160,
+    pub slot: U256,
+    pub value: H256,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct EnsResolved {
+    pub name: String,
+    pub address: Option<H160>,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct EnsReverseResolved {
+    pub address: H160,
+    pub name: Option<String>,
+}
+
+/// One chunk of `EthWallet::get_logs`, `done` on the last one.
+#[derive(Event, Clone, Debug)]
+pub struct LogsFetched {
+    pub logs: Vec<Log>,
+    pub done: bool,
+}
+
+/// Fired at startup when `EthWallet::provider_status` is `Missing`, e.g. to
+/// ask the player to install a wallet.
+#[derive(Event, Clone, Debug)]
+pub struct ProviderMissing;
+
+/// The decoded result of `EthWallet::call_contract`.
+#[derive(Event, Clone, Debug)]
+pub struct ContractCallReturned {
+    pub id: RequestId,
+    pub method: String,
+    pub tokens: Vec<Token>,
+}
+
+/// The transaction of `EthWallet::send_and_confirm` was mined, with enough
+/// confirmations if requested.
+#[derive(Event, Clone, Debug)]
+pub struct ConfirmedTransaction {
+    pub id: RequestId,
+    pub hash: H256,
+    pub receipt: TxReceipt,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct FeesSuggested(pub FeeSuggestion);
+
+#[derive(Event, Clone, Debug)]
+pub struct TokenMetadataFetched(pub TokenMetadata);
+
+/// An EIP-2612 permit from `Erc20::sign_permit`.
+#[derive(Event, Clone, Debug)]
+pub struct PermitSigned(pub PermitSignature);
+
+#[derive(Event, Debug)]
+pub struct OperationFailed(pub Web3OpError);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalletOp {
+    Connect,
+    Sign,
+    Send,
+    Call,
+    SwitchChain,
+    AddChain,
+    Balance,
+    WatchTransaction,
+    PersonalSign,
+    SignTypedData,
+    #[cfg(feature = "ws")]
+    WsConnect,
+    EstimateGas,
+    #[cfg(feature = "walletconnect")]
+    WalletConnect,
+    WatchLogs,
+    #[cfg(feature = "ws")]
+    WsSubscribe,
+    WatchAsset,
+    Multicall,
+    Permissions,
+    Nonce,
+    BlockNumber,
+    WatchBlocks,
+    GetTransaction,
+    GetReceipt,
+    GasPrice,
+    FeeHistory,
+    GetBlock,
+    GetCode,
+    Replace,
+    GetStorage,
+    Ens,
+    GetLogs,
+    SuggestFees,
+    Permit,
+}
+
+/// Returned by `sign`, `send` and `call`, and delivered with their result
+/// (e.g. `recv_call_with_id`) to tell concurrent requests apart.
+pub type RequestId = u64;
+
+/// EIP-1193 error code returned when the user rejects a request.
+pub const USER_REJECTED: i64 = 4001;
+/// EIP-1193 error code returned when the account or method is not authorized.
+pub const UNAUTHORIZED: i64 = 4100;
+/// EIP-1193 error code returned when the wallet does not support the method.
+pub const UNSUPPORTED_METHOD: i64 = 4200;
+/// EIP-1193 error code returned when the wallet is disconnected from all chains.
+pub const DISCONNECTED: i64 = 4900;
+/// EIP-1193 error code returned when the wallet is not connected to the chain.
+pub const CHAIN_DISCONNECTED: i64 = 4901;
+/// EIP-3326 error code returned when the wallet does not know the requested chain.
+pub const UNRECOGNIZED_CHAIN: i64 = 4902;
+/// Returned by MetaMask while the same kind of request already awaits the user.
+pub const REQUEST_PENDING: i64 = -32002;
+
+/// Errors of the wallet operations, delivered through `EthWallet::recv_error`.
+pub type WalletError = Web3Error;
+
+#[derive(Debug)]
+pub enum Web3Error {
+    /// No injected EIP-1193 provider (e.g. MetaMask) was found.
+    ProviderUnavailable,
+    UserRejected,
+    Unauthorized,
+    UnsupportedMethod,
+    Disconnected,
+    UnrecognizedChain,
+    /// A previous request of the same kind is still waiting for the user.
+    RequestPending,
+    Rpc(web3::Error),
+    Abi(ContractError),
+    Parse(FromHexError),
+    InvalidParams(String),
+    Timeout,
+    /// The watched transaction left the block it was mined in.
+    Reorged(H256),
+    /// There is no `IoTaskPool` to run the request on, see `TaskPoolPolicy`.
+    TaskPoolUnavailable,
+    /// The call reverted. `reason` is set for standard `Error(string)` reverts,
+    /// `data` is the raw revert payload (e.g. a custom error to decode against the abi).
+    Reverted {
+        reason: Option<String>,
+        data: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Web3Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Web3Error::ProviderUnavailable => write!(f, "no injected eip-1193 provider"),
+            Web3Error::UserRejected => write!(f, "user rejected the request"),
+            Web3Error::Unauthorized => write!(f, "request not authorized by the wallet"),
+            Web3Error::UnsupportedMethod => write!(f, "method not supported by the wallet"),
+            Web3Error::Disconnected => write!(f, "wallet is disconnected"),
+            Web3Error::UnrecognizedChain => write!(f, "chain is not added to the wallet"),
+            Web3Error::RequestPending => write!(f, "a request is already pending in the wallet"),
+            Web3Error::Rpc(e) => write!(f, "{}", e),
+            Web3Error::Abi(e) => write!(f, "{}", e),
+            Web3Error::Parse(e) => write!(f, "invalid address: {}", e),
+            Web3Error::InvalidParams(e) => write!(f, "invalid params: {}", e),
+            Web3Error::Timeout => write!(f, "timed out"),
+            Web3Error::Reorged(hash) => write!(f, "transaction {:?} was reorged out", hash),
+            Web3Error::TaskPoolUnavailable => write!(f, "the IoTaskPool is not initialized"),
+            Web3Error::Reverted {
+                reason: Some(reason),
+                ..
+            } => write!(f, "execution reverted: {}", reason),
+            Web3Error::Reverted { data, .. } => {
+                write!(f, "execution reverted: 0x{}", data.to_hex::<String>())
+            }
+        }
     }
+}
 
-    pub fn sign(&self, account: &str, msg: String) {
-        let account = account.parse().unwrap();
+impl std::error::Error for Web3Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Web3Error::Rpc(e) => Some(e),
+            Web3Error::Abi(e) => Some(e),
+            Web3Error::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-        let tx = self.signature_tx.clone();
-        IoTaskPool::get_or_init(TaskPool::new)
-            .spawn(async move {
-                let provider = eip_1193::Provider::default().unwrap().unwrap();
-                let transport = eip_1193::Eip1193::new(provider);
-                let web3 = web3::Web3::new(transport);
-
-                let msg = web3::types::Bytes(msg.as_bytes().to_vec());
-                let signature = web3.eth().sign(account, msg).await.unwrap();
-                let _ = tx.send(signature).await;
-            })
-            .detach();
+impl Web3Error {
+    /// A timeout or transport failure, which may not happen again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Web3Error::Timeout
+                | Web3Error::Rpc(web3::Error::Transport(_) | web3::Error::Unreachable)
+        )
     }
+}
+
+impl From<web3::Error> for Web3Error {
+    fn from(e: web3::Error) -> Web3Error {
+        if let Some(data) = revert_data(&e) {
+            return Web3Error::Reverted {
+                reason: decode_revert_reason(&data),
+                data,
+            };
+        }
 
-    pub fn send(&self, from: &str, to: H160, data: Vec<u8>) {
-        let from = from.parse().unwrap();
+        let web3::Error::Rpc(rpc) = &e else {
+            return Web3Error::Rpc(e);
+        };
+        match rpc.code.code() {
+            USER_REJECTED => Web3Error::UserRejected,
+            UNAUTHORIZED => Web3Error::Unauthorized,
+            UNSUPPORTED_METHOD => Web3Error::UnsupportedMethod,
+            DISCONNECTED | CHAIN_DISCONNECTED => Web3Error::Disconnected,
+            UNRECOGNIZED_CHAIN => Web3Error::UnrecognizedChain,
+            REQUEST_PENDING => Web3Error::RequestPending,
+            _ => Web3Error::Rpc(e),
+        }
+    }
+}
 
-        let tx = self.transaction_tx.clone();
-        IoTaskPool::get_or_init(TaskPool::new)
-            .spawn(async move {
-                let provider = eip_1193::Provider::default().unwrap().unwrap();
-                let transport = eip_1193::Eip1193::new(provider);
-                let web3 = web3::Web3::new(transport);
+/// Revert payload of a failed call. Wallets put it in the rpc error data,
+/// either directly or nested in the original node error.
+fn revert_data(e: &web3::Error) -> Option<Vec<u8>> {
+    let web3::Error::Rpc(rpc) = e else {
+        return None;
+    };
+    let data = rpc.data.as_ref()?;
+    let hex = data
+        .as_str()
+        .or_else(|| data["data"].as_str())
+        .or_else(|| data["originalError"]["data"].as_str())?;
+    hex.strip_prefix("0x")?.from_hex().ok()
+}
 
-                let mut txr = TransactionRequest::default();
-                txr.from = from;
-                txr.to = Some(to);
-                txr.data = Some(data.into());
+/// The message of a standard `Error(string)` or `Panic(uint256)` revert
+/// payload, see `Contract::decode_revert` for custom errors.
+///
+/// ```
+/// use bevy_web3::decode_revert_reason;
+/// use rustc_hex::FromHex;
+///
+/// let error: Vec<u8> = concat!(
+///     "08c379a0",
+///     "0000000000000000000000000000000000000000000000000000000000000020",
+///     "0000000000000000000000000000000000000000000000000000000000000014",
+///     "696e73756666696369656e742062616c616e6365000000000000000000000000",
+/// )
+/// .from_hex()
+/// .unwrap();
+/// assert_eq!(decode_revert_reason(&error).unwrap(), "insufficient balance");
+///
+/// let panic: Vec<u8> = concat!(
+///     "4e487b71",
+///     "0000000000000000000000000000000000000000000000000000000000000011",
+/// )
+/// .from_hex()
+/// .unwrap();
+/// assert_eq!(
+///     decode_revert_reason(&panic).unwrap(),
+///     "panic 0x11: arithmetic overflow or underflow"
+/// );
+/// ```
+pub fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+    const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
 
-                let hash = web3.eth().send_transaction(txr).await.unwrap();
-                let _ = tx.send(hash).await;
-            })
-            .detach();
+    if data.len() < 4 {
+        return None;
     }
+    let (selector, args) = data.split_at(4);
+    if selector == ERROR_SELECTOR {
+        return ethabi::decode(&[ParamType::String], args)
+            .ok()?
+            .pop()?
+            .into_string();
+    }
+    if selector != PANIC_SELECTOR {
+        return None;
+    }
+    let code = ethabi::decode(&[ParamType::Uint(256)], args)
+        .ok()?
+        .pop()?
+        .into_uint()?;
+    let reason = match code.low_u32() {
+        _ if code > U256::from(0xff) => "unknown panic",
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function",
+        _ => "unknown panic",
+    };
+    Some(format!("panic {:#04x}: {}", code.low_u32(), reason))
+}
 
-    pub fn call(&self, to: H160, method: String, data: Vec<u8>) {
-        let tx = self.call_tx.clone();
-        IoTaskPool::get_or_init(TaskPool::new)
-            .spawn(async move {
-                let provider = eip_1193::Provider::default().unwrap().unwrap();
-                let transport = eip_1193::Eip1193::new(provider);
-                let web3 = web3::Web3::new(transport);
+/// `name(type,...)` as hashed into the selector of `function`.
+fn canonical(function: &Function) -> String {
+    let inputs: Vec<String> = function.inputs.iter().map(|p| p.kind.to_string()).collect();
+    format!("{}({})", function.name, inputs.join(","))
+}
 
-                let mut call = CallRequest::default();
-                call.to = Some(to);
-                call.data = Some(data.into());
-                let bytes = web3.eth().call(call, None).await.unwrap();
+/// `token` as Solidity would write it, e.g. numbers in decimal.
+fn format_token(token: &Token) -> String {
+    let list = |tokens: &[Token]| {
+        tokens
+            .iter()
+            .map(format_token)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match token {
+        Token::Address(address) => format!("{:?}", address),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", bytes.to_hex::<String>()),
+        Token::Int(value) if value.bit(255) => {
+            format!("-{}", (!*value).overflowing_add(1.into()).0)
+        }
+        Token::Int(value) | Token::Uint(value) => value.to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => format!("{:?}", value),
+        Token::Array(tokens) | Token::FixedArray(tokens) => format!("[{}]", list(tokens)),
+        Token::Tuple(tokens) => format!("({})", list(tokens)),
+    }
+}
 
-                let _ = tx.send((method, bytes.0)).await;
-            })
-            .detach();
+impl From<serde_json::Error> for Web3Error {
+    fn from(e: serde_json::Error) -> Web3Error {
+        Web3Error::Rpc(e.into())
     }
+}
 
-    pub fn recv_account(&mut self) -> Result<(String, u64), RecvError> {
-        let (addrs, chain) = self.account_rx.try_recv()?;
-        self.accounts = addrs;
-        self.chain_id = chain;
+impl From<ContractError> for Web3Error {
+    fn from(e: ContractError) -> Web3Error {
+        Web3Error::Abi(e)
+    }
+}
 
-        let addr = PeerId(self.accounts[0].to_fixed_bytes());
-        Ok((addr.to_hex(), chain))
+impl From<FromHexError> for Web3Error {
+    fn from(e: FromHexError) -> Web3Error {
+        Web3Error::Parse(e)
     }
+}
 
-    pub fn recv_signature(&self) -> Result<H520, RecvError> {
-        Ok(self.signature_rx.try_recv()?)
+/// A failed async wallet operation.
+#[derive(Debug)]
+pub struct Web3OpError {
+    pub op: WalletOp,
+    /// The id returned by the failed request, for those returning one.
+    pub id: Option<RequestId>,
+    pub error: Web3Error,
+}
+
+impl Web3OpError {
+    /// The wallet has no such chain configured, it must be added before switching.
+    pub fn is_unrecognized_chain(&self) -> bool {
+        matches!(self.error, Web3Error::UnrecognizedChain)
     }
 
-    pub fn recv_transaction(&self) -> Result<H256, RecvError> {
-        Ok(self.transaction_rx.try_recv()?)
+    /// The user declined the request in the wallet, an expected outcome the
+    /// game should handle (re-prompt or cancel) rather than report.
+    pub fn is_user_rejected(&self) -> bool {
+        matches!(self.error, Web3Error::UserRejected)
     }
+}
 
-    pub fn recv_call(&self) -> Result<(String, Vec<u8>), RecvError> {
-        Ok(self.call_rx.try_recv()?)
+impl fmt::Display for Web3OpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} failed: {}", self.op, self.error)
     }
 }
 
-#[derive(Default)]
-pub struct Contract {
+impl std::error::Error for Web3OpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// EIP-3085 `wallet_addEthereumChain` parameters.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddChainParams {
+    #[serde(serialize_with = "serialize_chain_id")]
+    pub chain_id: u64,
+    pub chain_name: String,
+    pub rpc_urls: Vec<String>,
+    pub native_currency: NativeCurrency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_explorer_urls: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+fn serialize_chain_id<S: Serializer>(chain_id: &u64, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("{:#x}", chain_id))
+}
+
+impl AddChainParams {
+    fn validate(&self) -> Result<(), String> {
+        if self.chain_id == 0 {
+            return Err("chain id must be non-zero".to_owned());
+        }
+        if self.rpc_urls.is_empty() {
+            return Err("at least one rpc url is required".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// EIP-747 `wallet_watchAsset` parameters.
+#[derive(Clone, Debug, Serialize)]
+pub struct WatchAssetParams {
+    #[serde(rename = "type")]
+    pub kind: AssetKind,
+    pub options: AssetOptions,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum AssetKind {
+    #[serde(rename = "ERC20")]
+    Erc20,
+    #[serde(rename = "ERC721")]
+    Erc721,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetOptions {
     pub address: H160,
-    abi: EthContract,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+    /// Url of the token image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Decimal token id, ERC721 only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
 }
 
-impl Contract {
-    pub fn is_empty(&self) -> bool {
-        self.address == H160::default()
+impl WatchAssetParams {
+    pub fn erc20(address: H160, symbol: &str, decimals: u8) -> Self {
+        WatchAssetParams {
+            kind: AssetKind::Erc20,
+            options: AssetOptions {
+                address,
+                symbol: Some(symbol.to_owned()),
+                decimals: Some(decimals),
+                ..Default::default()
+            },
+        }
     }
 
-    pub fn address(&self) -> String {
-        let addr = PeerId(self.address.to_fixed_bytes());
-        addr.to_hex()
+    pub fn erc721(address: H160, token_id: U256) -> Self {
+        WatchAssetParams {
+            kind: AssetKind::Erc721,
+            options: AssetOptions {
+                address,
+                token_id: Some(token_id.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn with_image(mut self, image: &str) -> Self {
+        self.options.image = Some(image.to_owned());
+        self
     }
+}
+
+#[derive(Clone, Debug)]
+pub struct TxReceipt {
+    pub hash: H256,
+    /// `true` if the transaction succeeded, `false` if it reverted.
+    pub status: bool,
+    pub block_number: U256,
+    pub gas_used: U256,
+    /// The deployed contract, for a transaction sent with `EthWallet::deploy`.
+    pub contract_address: Option<H160>,
+}
 
-    pub fn load(address: &str, json: &[u8]) -> Self {
-        let address = address.parse().unwrap();
-        let abi = EthContract::load(json).unwrap();
-        Contract { address, abi }
+impl From<TransactionReceipt> for TxReceipt {
+    fn from(r: TransactionReceipt) -> TxReceipt {
+        TxReceipt {
+            hash: r.transaction_hash,
+            status: r.status.map(|s| s.as_u64() == 1).unwrap_or(false),
+            block_number: r
+                .block_number
+                .map(|n| n.as_u64().into())
+                .unwrap_or_default(),
+            gas_used: r.gas_used.unwrap_or_default(),
+            contract_address: r.contract_address,
+        }
     }
+}
 
-    pub fn encode(&self, method: &str, tokens: &[Token]) -> Vec<u8> {
-        self.abi
-            .function(method)
-            .unwrap()
-            .encode_input(tokens)
-            .unwrap()
+/// How `EthWallet::watch_transaction` polls for a receipt.
+#[derive(Clone, Copy, Debug)]
+pub struct WatchOptions {
+    pub interval: Duration,
+    pub max_attempts: u32,
+    /// Blocks to wait for on top of the one including the transaction, 0 to
+    /// report it as soon as it is mined.
+    pub confirmations: u64,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            interval: Duration::from_secs(2),
+            max_attempts: 60,
+            confirmations: 0,
+        }
     }
+}
 
-    pub fn decode(&self, method: &str, bytes: &[u8]) -> Vec<Token> {
-        self.abi
-            .function(method)
-            .unwrap()
-            .decode_output(bytes)
-            .unwrap()
-            .into()
+/// Bounds every request so a provider that never answers (e.g. a wallet popup
+/// left open) can't leave the game waiting forever.
+#[derive(Clone, Copy, Debug)]
+pub struct OperationPolicy {
+    /// After this long a request fails with `Web3Error::Timeout`. Watchers
+    /// (`watch_transaction`, `watch_blocks`, the `accountsChanged` listener,
+    /// ...) are not bounded, only each of their requests. WalletConnect
+    /// pairing and the websocket connection have their own, longer limits.
+    pub timeout: Duration,
+    /// Attempts after the first for reads which timed out or hit a transport
+    /// error. Signing and sending are never retried, the wallet may have
+    /// acted on the first attempt.
+    pub retries: u32,
+}
+
+impl Default for OperationPolicy {
+    fn default() -> Self {
+        OperationPolicy {
+            timeout: Duration::from_secs(120),
+            retries: 2,
+        }
+    }
+}
+
+/// Blocks queried per `eth_getLogs` request by `EthWallet::get_logs`, most
+/// nodes reject larger ranges or cap the number of results.
+pub const LOG_BLOCK_RANGE: u64 = 2_000;
+
+/// Query for `EthWallet::get_logs`.
+#[derive(Clone, Debug, Default)]
+pub struct LogFilter {
+    /// Empty matches logs of any contract.
+    pub addresses: Vec<H160>,
+    /// Up to 4 topic positions, `None` matches any value and a list matches
+    /// any of its topics.
+    pub topics: Vec<Option<Vec<H256>>>,
+    pub from_block: u64,
+    /// The latest block if `None`.
+    pub to_block: Option<u64>,
+}
+
+impl LogFilter {
+    pub fn new(from_block: u64) -> Self {
+        LogFilter {
+            from_block,
+            ..Default::default()
+        }
+    }
+
+    pub fn address(mut self, address: H160) -> Self {
+        self.addresses.push(address);
+        self
+    }
+
+    /// Match `topics` at position `index`, e.g. 0 for the event signature.
+    pub fn topic(mut self, index: usize, topics: Vec<H256>) -> Self {
+        if self.topics.len() <= index {
+            self.topics.resize(index + 1, None);
+        }
+        self.topics[index] = Some(topics);
+        self
+    }
+
+    pub fn to_block(mut self, to_block: u64) -> Self {
+        self.to_block = Some(to_block);
+        self
+    }
+
+    /// The `web3` filter for the blocks `from..=to`.
+    pub fn filter(&self, from: u64, to: u64) -> Filter {
+        let topic = |i: usize| self.topics.get(i).cloned().flatten();
+        let mut builder = FilterBuilder::default()
+            .topics(topic(0), topic(1), topic(2), topic(3))
+            .from_block(BlockNumber::Number(from.into()))
+            .to_block(BlockNumber::Number(to.into()));
+        if !self.addresses.is_empty() {
+            builder = builder.address(self.addresses.clone());
+        }
+        builder.build()
+    }
+}
+
+/// EIP-1559 fees from `EthWallet::suggest_fees`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeSuggestion {
+    /// Base fee of the next block.
+    pub base_fee: U256,
+    pub max_priority_fee: U256,
+    /// Twice the base fee plus the priority fee, enough for the base fee to
+    /// rise for a few full blocks.
+    pub max_fee: U256,
+}
+
+impl FeeSuggestion {
+    /// `SendOptions` paying these fees.
+    pub fn options(&self) -> SendOptions {
+        SendOptions {
+            max_fee_per_gas: Some(self.max_fee),
+            max_priority_fee_per_gas: Some(self.max_priority_fee),
+            ..Default::default()
+        }
+    }
+}
+
+/// Optional transaction fields for `EthWallet::send_with`, unset fields are
+/// filled in by the wallet.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SendOptions {
+    pub value: Option<U256>,
+    pub gas: Option<U256>,
+    pub gas_price: Option<U256>,
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    pub nonce: Option<U256>,
+}
+
+impl SendOptions {
+    pub(crate) fn is_eip1559(&self) -> bool {
+        self.max_fee_per_gas.is_some() || self.max_priority_fee_per_gas.is_some()
+    }
+
+    fn request(&self, from: H160, to: Option<H160>, data: Vec<u8>) -> TransactionRequest {
+        TransactionRequest {
+            from,
+            to,
+            data: Some(data.into()),
+            value: self.value,
+            gas: self.gas,
+            gas_price: self.gas_price,
+            max_fee_per_gas: self.max_fee_per_gas,
+            max_priority_fee_per_gas: self.max_priority_fee_per_gas,
+            nonce: self.nonce,
+            transaction_type: self.is_eip1559().then(|| 2.into()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderStatus {
+    Available,
+    Missing,
+}
+
+#[derive(Resource)]
+pub struct EthWallet {
+    pub accounts: Vec<H160>,
+    pub chain_id: u64,
+    transport: TransportConfig,
+    read_transport: Option<TransportConfig>,
+    watch_options: WatchOptions,
+    multicall_address: H160,
+    ens_registry: H160,
+    policy: OperationPolicy,
+    watched: Mutex<Option<TransportConfig>>,
+    session: Arc<AtomicU64>,
+    /// Connect results read so far, for `switch_to_default_chain`.
+    connections: u64,
+    next_id: AtomicU64,
+    sent: Arc<Mutex<Vec<Transaction>>>,
+    /// By chain id and token address.
+    token_cache: Arc<Mutex<HashMap<(u64, H160), erc20::TokenInfo>>>,
+    #[cfg(feature = "ws")]
+    ws: ws::WsState,
+    /// Requests waiting for `WalletTasks::run` to start them.
+    tasks_tx: Sender<WalletTask>,
+    tasks_rx: Receiver<WalletTask>,
+    account_tx: Sender<(Vec<H160>, u64)>,
+    account_rx: Receiver<(Vec<H160>, u64)>,
+    signature_tx: Sender<(RequestId, H520)>,
+    signature_rx: Receiver<(RequestId, H520)>,
+    transaction_tx: Sender<(RequestId, H256)>,
+    transaction_rx: Receiver<(RequestId, H256)>,
+    call_tx: Sender<(RequestId, String, Vec<u8>)>,
+    call_rx: Receiver<(RequestId, String, Vec<u8>)>,
+    /// Call results read past by `recv_call_by_id`, in arrival order.
+    parked_calls: Mutex<VecDeque<(RequestId, String, Vec<u8>)>>,
+    switch_chain_tx: Sender<u64>,
+    switch_chain_rx: Receiver<u64>,
+    add_chain_tx: Sender<u64>,
+    add_chain_rx: Receiver<u64>,
+    balance_tx: Sender<U256>,
+    balance_rx: Receiver<U256>,
+    receipt_tx: Sender<TxReceipt>,
+    receipt_rx: Receiver<TxReceipt>,
+    personal_signature_tx: Sender<(RequestId, H520)>,
+    personal_signature_rx: Receiver<(RequestId, H520)>,
+    typed_signature_tx: Sender<(RequestId, H520)>,
+    typed_signature_rx: Receiver<(RequestId, H520)>,
+    gas_estimate_tx: Sender<U256>,
+    gas_estimate_rx: Receiver<U256>,
+    log_tx: Sender<Log>,
+    log_rx: Receiver<Log>,
+    accounts_changed_tx: Sender<Vec<H160>>,
+    accounts_changed_rx: Receiver<Vec<H160>>,
+    chain_changed_tx: Sender<u64>,
+    chain_changed_rx: Receiver<u64>,
+    disconnected_tx: Sender<()>,
+    disconnected_rx: Receiver<()>,
+    asset_watched_tx: Sender<bool>,
+    asset_watched_rx: Receiver<bool>,
+    multicall_tx: Sender<Vec<(bool, Vec<u8>)>>,
+    multicall_rx: Receiver<Vec<(bool, Vec<u8>)>>,
+    permissions_tx: Sender<Vec<String>>,
+    permissions_rx: Receiver<Vec<String>>,
+    nonce_tx: Sender<U256>,
+    nonce_rx: Receiver<U256>,
+    block_number_tx: Sender<U256>,
+    block_number_rx: Receiver<U256>,
+    new_block_tx: Sender<NewBlock>,
+    new_block_rx: Receiver<NewBlock>,
+    tx_details_tx: Sender<Option<Transaction>>,
+    tx_details_rx: Receiver<Option<Transaction>>,
+    full_receipt_tx: Sender<Option<TransactionReceipt>>,
+    full_receipt_rx: Receiver<Option<TransactionReceipt>>,
+    gas_price_tx: Sender<U256>,
+    gas_price_rx: Receiver<U256>,
+    fee_history_tx: Sender<FeeHistory>,
+    fee_history_rx: Receiver<FeeHistory>,
+    block_tx: Sender<Option<Block<H256>>>,
+    block_rx: Receiver<Option<Block<H256>>>,
+    confirmed_tx: Sender<TxReceipt>,
+    confirmed_rx: Receiver<TxReceipt>,
+    code_tx: Sender<(H160, Vec<u8>)>,
+    code_rx: Receiver<(H160, Vec<u8>)>,
+    replaced_tx: Sender<TransactionReplaced>,
+    replaced_rx: Receiver<TransactionReplaced>,
+    storage_tx: Sender<(H160, U256, H256)>,
+    storage_rx: Receiver<(H160, U256, H256)>,
+    ens_address_tx: Sender<(String, Option<H160>)>,
+    ens_address_rx: Receiver<(String, Option<H160>)>,
+    ens_name_tx: Sender<(H160, Option<String>)>,
+    ens_name_rx: Receiver<(H160, Option<String>)>,
+    logs_tx: Sender<(Vec<Log>, bool)>,
+    logs_rx: Receiver<(Vec<Log>, bool)>,
+    contract_call_tx: Sender<(RequestId, String, Vec<Token>)>,
+    contract_call_rx: Receiver<(RequestId, String, Vec<Token>)>,
+    send_confirmed_tx: Sender<(RequestId, H256, TxReceipt)>,
+    send_confirmed_rx: Receiver<(RequestId, H256, TxReceipt)>,
+    fees_tx: Sender<FeeSuggestion>,
+    fees_rx: Receiver<FeeSuggestion>,
+    token_metadata_tx: Sender<TokenMetadata>,
+    token_metadata_rx: Receiver<TokenMetadata>,
+    permit_tx: Sender<PermitSignature>,
+    permit_rx: Receiver<PermitSignature>,
+    error_tx: Sender<Web3OpError>,
+    error_rx: Receiver<Web3OpError>,
+}
+
+fn init_eth_wallet(
+    mut commands: Commands,
+    config: Res<WalletConfig>,
+    mut tasks: NonSendMut<WalletTasks>,
+) {
+    if tasks.pool().is_none() {
+        panic!("WalletPlugin requires the IoTaskPool, add TaskPoolPlugin before it")
+    }
+
+    let mut wallet = EthWallet::new(config.transport.clone());
+    wallet.watch_options.interval = config.receipt_poll_interval;
+    wallet.multicall_address = config.multicall_address;
+    wallet.ens_registry = config.ens_registry;
+    wallet.policy = config.operation_policy;
+    commands.insert_resource(wallet);
+}
+
+/// A request queued by `EthWallet`, see `WalletTasks`.
+struct WalletTask {
+    op: WalletOp,
+    id: Option<RequestId>,
+    start: Box<dyn FnOnce(Transports) -> LocalBoxFuture<'static, ()> + Send>,
+}
+
+/// Starts the requests of `EthWallet` on the `IoTaskPool`. The transports are
+/// not `Send`, so they are built once and kept here, and the requests run on
+/// the thread calling `run`. `WalletPlugin` inserts one as a non-send resource
+/// and runs it every frame on the main thread, whose local executor
+/// `TaskPoolPlugin` ticks.
+///
+/// Without the plugin, e.g. with `EthWallet::new` on a server, keep one on the
+/// thread driving the wallet and call `run` regularly. On native, also tick
+/// that thread's local executor (`IoTaskPool::get().with_local_executor`).
+#[derive(Default)]
+pub struct WalletTasks {
+    policy: TaskPoolPolicy,
+    transports: Transports,
+    /// Tick the local executor in `run`, for a pool `TaskPoolPlugin` doesn't tick.
+    tick: bool,
+    /// The local tasks are cancelled when dropped, keep them until they finish.
+    #[cfg(not(target_arch = "wasm32"))]
+    running: Vec<Task<()>>,
+}
+
+impl WalletTasks {
+    /// Tasks following `policy` when the `IoTaskPool` is missing.
+    pub fn new(policy: TaskPoolPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// The `IoTaskPool`, created if missing and `policy` allows it.
+    fn pool(&mut self) -> Option<&'static TaskPool> {
+        if let Some(pool) = IoTaskPool::try_get() {
+            return Some(pool);
+        }
+        match self.policy {
+            TaskPoolPolicy::RequireExisting => None,
+            TaskPoolPolicy::CreateDefault => {
+                warn!("IoTaskPool is not initialized, creating a default one for EthWallet");
+                // there is no `TaskPoolPlugin` to tick it
+                self.tick = true;
+                Some(IoTaskPool::get_or_init(TaskPool::new))
+            }
+        }
+    }
+
+    /// Start the requests `wallet` queued since the last call. Without a pool
+    /// they fail with `Web3Error::TaskPoolUnavailable`.
+    pub fn run(&mut self, wallet: &EthWallet) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.running.retain(|task| !task.is_finished());
+
+        let Some(pool) = self.pool() else {
+            while let Ok(WalletTask { op, id, .. }) = wallet.tasks_rx.try_recv() {
+                let error = Web3Error::TaskPoolUnavailable;
+                let _ = wallet.error_tx.try_send(Web3OpError { op, id, error });
+            }
+            return;
+        };
+        while let Ok(task) = wallet.tasks_rx.try_recv() {
+            let future = (task.start)(self.transports.clone());
+            #[cfg(target_arch = "wasm32")]
+            pool.spawn(future).detach();
+            // the native pool's `spawn` requires `Send`, run it on the local
+            // executor of this thread instead
+            #[cfg(not(target_arch = "wasm32"))]
+            self.running.push(Task::new(
+                pool.with_local_executor(|executor| executor.spawn(future)),
+            ));
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.tick {
+            pool.with_local_executor(|executor| while executor.try_tick() {});
+        }
+    }
+}
+
+fn run_wallet_tasks(wallet: Res<EthWallet>, mut tasks: NonSendMut<WalletTasks>) {
+    tasks.run(&wallet);
+}
+
+fn detect_provider(wallet: Res<EthWallet>, mut events: EventWriter<ProviderMissing>) {
+    if wallet.provider_status() == ProviderStatus::Missing {
+        events.send(ProviderMissing);
+    }
+}
+
+fn auto_connect(config: Res<WalletConfig>, wallet: Res<EthWallet>) {
+    if config.auto_connect {
+        wallet.silent_connect();
+    }
+}
+
+fn events_enabled(config: Res<WalletConfig>) -> bool {
+    config.emit_events
+}
+
+/// Runs after each connect result read, by `emit_wallet_events` or by the
+/// game with `recv_account`.
+fn switch_to_default_chain(
+    config: Res<WalletConfig>,
+    wallet: Res<EthWallet>,
+    mut connections: Local<u64>,
+) {
+    let Some(chain_id) = config.default_chain else {
+        return;
+    };
+    if wallet.connections != *connections {
+        *connections = wallet.connections;
+        if !wallet.accounts.is_empty() && wallet.chain_id != chain_id {
+            wallet.switch_chain(chain_id);
+        }
+    }
+}
+
+/// Send read-only requests (`call`, `balance`, ...) to a dedicated node instead
+/// of the wallet, transactions and signatures still go through the wallet.
+#[cfg(feature = "http")]
+#[derive(Resource, Clone, Debug, Default)]
+pub struct RpcConfig {
+    pub read_url: Option<String>,
+}
+
+#[cfg(feature = "http")]
+fn sync_rpc_config(config: Res<RpcConfig>, mut wallet: ResMut<EthWallet>) {
+    if config.is_changed() {
+        wallet.read_transport = config.read_url.as_ref().map(|url| TransportConfig::Http {
+            url: url.clone(),
+            local_key: None,
+        });
+    }
+}
+
+/// Any of the events `WalletPlugin` fires, returned by `EthWallet::poll`.
+#[derive(Debug)]
+pub enum WalletEvent {
+    AccountConnected(AccountConnected),
+    MessageSigned(MessageSigned),
+    TransactionSent(TransactionSent),
+    CallReturned(CallReturned),
+    ChainSwitched(ChainSwitched),
+    ChainAdded(ChainAdded),
+    BalanceFetched(BalanceFetched),
+    TransactionMined(TransactionMined),
+    PersonalMessageSigned(PersonalMessageSigned),
+    TypedDataSigned(TypedDataSigned),
+    GasEstimated(GasEstimated),
+    LogReceived(LogReceived),
+    AccountsChanged(AccountsChanged),
+    ChainChanged(ChainChanged),
+    WalletDisconnected(WalletDisconnected),
+    AssetWatched(AssetWatched),
+    MulticallReturned(MulticallReturned),
+    PermissionsChanged(PermissionsChanged),
+    NonceFetched(NonceFetched),
+    BlockNumberFetched(BlockNumberFetched),
+    NewBlock(NewBlock),
+    TransactionFetched(TransactionFetched),
+    ReceiptFetched(ReceiptFetched),
+    GasPriceFetched(GasPriceFetched),
+    FeeHistoryFetched(FeeHistoryFetched),
+    BlockFetched(Box<BlockFetched>),
+    TransactionConfirmed(TransactionConfirmed),
+    CodeFetched(CodeFetched),
+    TransactionReplaced(TransactionReplaced),
+    StorageFetched(StorageFetched),
+    EnsResolved(EnsResolved),
+    EnsReverseResolved(EnsReverseResolved),
+    LogsFetched(LogsFetched),
+    ContractCallReturned(ContractCallReturned),
+    ConfirmedTransaction(ConfirmedTransaction),
+    FeesSuggested(FeesSuggested),
+    TokenMetadataFetched(TokenMetadataFetched),
+    PermitSigned(PermitSigned),
+    OperationFailed(OperationFailed),
+}
+
+impl WalletEvent {
+    /// The `RequestId` of the request this event answers, to route the
+    /// results and errors of concurrent `sign`, `send` and `call` requests.
+    pub fn request_id(&self) -> Option<RequestId> {
+        match self {
+            WalletEvent::MessageSigned(e) => Some(e.id),
+            WalletEvent::TransactionSent(e) => Some(e.id),
+            WalletEvent::CallReturned(e) => Some(e.id),
+            WalletEvent::PersonalMessageSigned(e) => Some(e.id),
+            WalletEvent::TypedDataSigned(e) => Some(e.id),
+            WalletEvent::ContractCallReturned(e) => Some(e.id),
+            WalletEvent::ConfirmedTransaction(e) => Some(e.id),
+            WalletEvent::OperationFailed(e) => e.0.id,
+            _ => None,
+        }
+    }
+}
+
+#[derive(SystemParam)]
+struct WalletEventWriters<'w> {
+    accounts: EventWriter<'w, AccountConnected>,
+    signatures: EventWriter<'w, MessageSigned>,
+    transactions: EventWriter<'w, TransactionSent>,
+    calls: EventWriter<'w, CallReturned>,
+    switches: EventWriter<'w, ChainSwitched>,
+    additions: EventWriter<'w, ChainAdded>,
+    balances: EventWriter<'w, BalanceFetched>,
+    receipts: EventWriter<'w, TransactionMined>,
+    personal_signatures: EventWriter<'w, PersonalMessageSigned>,
+    typed_signatures: EventWriter<'w, TypedDataSigned>,
+    gas_estimates: EventWriter<'w, GasEstimated>,
+    logs: EventWriter<'w, LogReceived>,
+    accounts_changed: EventWriter<'w, AccountsChanged>,
+    chain_changed: EventWriter<'w, ChainChanged>,
+    disconnected: EventWriter<'w, WalletDisconnected>,
+    assets_watched: EventWriter<'w, AssetWatched>,
+    multicalls: EventWriter<'w, MulticallReturned>,
+    permissions: EventWriter<'w, PermissionsChanged>,
+    nonces: EventWriter<'w, NonceFetched>,
+    block_numbers: EventWriter<'w, BlockNumberFetched>,
+    new_blocks: EventWriter<'w, NewBlock>,
+    tx_details: EventWriter<'w, TransactionFetched>,
+    full_receipts: EventWriter<'w, ReceiptFetched>,
+    gas_prices: EventWriter<'w, GasPriceFetched>,
+    fee_histories: EventWriter<'w, FeeHistoryFetched>,
+    blocks: EventWriter<'w, BlockFetched>,
+    confirmed: EventWriter<'w, TransactionConfirmed>,
+    codes: EventWriter<'w, CodeFetched>,
+    replaced: EventWriter<'w, TransactionReplaced>,
+    storage: EventWriter<'w, StorageFetched>,
+    ens_addresses: EventWriter<'w, EnsResolved>,
+    ens_names: EventWriter<'w, EnsReverseResolved>,
+    log_chunks: EventWriter<'w, LogsFetched>,
+    contract_calls: EventWriter<'w, ContractCallReturned>,
+    send_confirmed: EventWriter<'w, ConfirmedTransaction>,
+    fees: EventWriter<'w, FeesSuggested>,
+    token_metadata: EventWriter<'w, TokenMetadataFetched>,
+    permits: EventWriter<'w, PermitSigned>,
+    errors: EventWriter<'w, OperationFailed>,
+}
+
+fn emit_wallet_events(mut wallet: ResMut<EthWallet>, mut events: WalletEventWriters) {
+    for event in wallet.poll() {
+        match event {
+            WalletEvent::AccountConnected(e) => {
+                events.accounts.send(e);
+            }
+            WalletEvent::MessageSigned(e) => {
+                events.signatures.send(e);
+            }
+            WalletEvent::TransactionSent(e) => {
+                events.transactions.send(e);
+            }
+            WalletEvent::CallReturned(e) => {
+                events.calls.send(e);
+            }
+            WalletEvent::ChainSwitched(e) => {
+                events.switches.send(e);
+            }
+            WalletEvent::ChainAdded(e) => {
+                events.additions.send(e);
+            }
+            WalletEvent::BalanceFetched(e) => {
+                events.balances.send(e);
+            }
+            WalletEvent::TransactionMined(e) => {
+                events.receipts.send(e);
+            }
+            WalletEvent::PersonalMessageSigned(e) => {
+                events.personal_signatures.send(e);
+            }
+            WalletEvent::TypedDataSigned(e) => {
+                events.typed_signatures.send(e);
+            }
+            WalletEvent::GasEstimated(e) => {
+                events.gas_estimates.send(e);
+            }
+            WalletEvent::LogReceived(e) => {
+                events.logs.send(e);
+            }
+            WalletEvent::AccountsChanged(e) => {
+                events.accounts_changed.send(e);
+            }
+            WalletEvent::ChainChanged(e) => {
+                events.chain_changed.send(e);
+            }
+            WalletEvent::WalletDisconnected(e) => {
+                events.disconnected.send(e);
+            }
+            WalletEvent::AssetWatched(e) => {
+                events.assets_watched.send(e);
+            }
+            WalletEvent::MulticallReturned(e) => {
+                events.multicalls.send(e);
+            }
+            WalletEvent::PermissionsChanged(e) => {
+                events.permissions.send(e);
+            }
+            WalletEvent::NonceFetched(e) => {
+                events.nonces.send(e);
+            }
+            WalletEvent::BlockNumberFetched(e) => {
+                events.block_numbers.send(e);
+            }
+            WalletEvent::NewBlock(e) => {
+                events.new_blocks.send(e);
+            }
+            WalletEvent::TransactionFetched(e) => {
+                events.tx_details.send(e);
+            }
+            WalletEvent::ReceiptFetched(e) => {
+                events.full_receipts.send(e);
+            }
+            WalletEvent::GasPriceFetched(e) => {
+                events.gas_prices.send(e);
+            }
+            WalletEvent::FeeHistoryFetched(e) => {
+                events.fee_histories.send(e);
+            }
+            WalletEvent::BlockFetched(e) => {
+                events.blocks.send(*e);
+            }
+            WalletEvent::TransactionConfirmed(e) => {
+                events.confirmed.send(e);
+            }
+            WalletEvent::CodeFetched(e) => {
+                events.codes.send(e);
+            }
+            WalletEvent::TransactionReplaced(e) => {
+                events.replaced.send(e);
+            }
+            WalletEvent::StorageFetched(e) => {
+                events.storage.send(e);
+            }
+            WalletEvent::EnsResolved(e) => {
+                events.ens_addresses.send(e);
+            }
+            WalletEvent::EnsReverseResolved(e) => {
+                events.ens_names.send(e);
+            }
+            WalletEvent::LogsFetched(e) => {
+                events.log_chunks.send(e);
+            }
+            WalletEvent::ContractCallReturned(e) => {
+                events.contract_calls.send(e);
+            }
+            WalletEvent::ConfirmedTransaction(e) => {
+                events.send_confirmed.send(e);
+            }
+            WalletEvent::FeesSuggested(e) => {
+                events.fees.send(e);
+            }
+            WalletEvent::TokenMetadataFetched(e) => {
+                events.token_metadata.send(e);
+            }
+            WalletEvent::PermitSigned(e) => {
+                events.permits.send(e);
+            }
+            WalletEvent::OperationFailed(e) => {
+                events.errors.send(e);
+            }
+        }
+    }
+}
+
+impl EthWallet {
+    /// A wallet sending its requests through `transport`, `WalletPlugin` inserts
+    /// one built from `WalletConfig`. Useful to drive it without the plugin, e.g.
+    /// from a headless server with `TransportConfig::Http`.
+    ///
+    /// Requests are queued and start when `WalletTasks::run` is called, which
+    /// `WalletPlugin` does every frame; without it keep a `WalletTasks` and
+    /// call `run` yourself.
+    pub fn new(transport: TransportConfig) -> Self {
+        let (tasks_tx, tasks_rx) = unbounded();
+        let (account_tx, account_rx) = unbounded();
+        let (signature_tx, signature_rx) = unbounded();
+        let (transaction_tx, transaction_rx) = unbounded();
+        let (call_tx, call_rx) = unbounded();
+        let (switch_chain_tx, switch_chain_rx) = unbounded();
+        let (add_chain_tx, add_chain_rx) = unbounded();
+        let (balance_tx, balance_rx) = unbounded();
+        let (receipt_tx, receipt_rx) = unbounded();
+        let (personal_signature_tx, personal_signature_rx) = unbounded();
+        let (typed_signature_tx, typed_signature_rx) = unbounded();
+        let (gas_estimate_tx, gas_estimate_rx) = unbounded();
+        let (log_tx, log_rx) = unbounded();
+        let (accounts_changed_tx, accounts_changed_rx) = unbounded();
+        let (chain_changed_tx, chain_changed_rx) = unbounded();
+        let (disconnected_tx, disconnected_rx) = unbounded();
+        let (asset_watched_tx, asset_watched_rx) = unbounded();
+        let (multicall_tx, multicall_rx) = unbounded();
+        let (permissions_tx, permissions_rx) = unbounded();
+        let (nonce_tx, nonce_rx) = unbounded();
+        let (block_number_tx, block_number_rx) = unbounded();
+        let (new_block_tx, new_block_rx) = unbounded();
+        let (tx_details_tx, tx_details_rx) = unbounded();
+        let (full_receipt_tx, full_receipt_rx) = unbounded();
+        let (gas_price_tx, gas_price_rx) = unbounded();
+        let (fee_history_tx, fee_history_rx) = unbounded();
+        let (block_tx, block_rx) = unbounded();
+        let (confirmed_tx, confirmed_rx) = unbounded();
+        let (code_tx, code_rx) = unbounded();
+        let (replaced_tx, replaced_rx) = unbounded();
+        let (storage_tx, storage_rx) = unbounded();
+        let (ens_address_tx, ens_address_rx) = unbounded();
+        let (ens_name_tx, ens_name_rx) = unbounded();
+        let (logs_tx, logs_rx) = unbounded();
+        let (contract_call_tx, contract_call_rx) = unbounded();
+        let (send_confirmed_tx, send_confirmed_rx) = unbounded();
+        let (fees_tx, fees_rx) = unbounded();
+        let (token_metadata_tx, token_metadata_rx) = unbounded();
+        let (permit_tx, permit_rx) = unbounded();
+        let (error_tx, error_rx) = unbounded();
+
+        EthWallet {
+            accounts: vec![],
+            chain_id: 0,
+            transport,
+            read_transport: None,
+            watch_options: WatchOptions::default(),
+            multicall_address: MULTICALL3,
+            ens_registry: ENS_REGISTRY,
+            policy: OperationPolicy::default(),
+            watched: Mutex::new(None),
+            session: Arc::new(AtomicU64::new(0)),
+            connections: 0,
+            next_id: AtomicU64::new(1),
+            sent: Arc::new(Mutex::new(vec![])),
+            token_cache: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "ws")]
+            ws: Default::default(),
+            tasks_tx,
+            tasks_rx,
+            account_tx,
+            account_rx,
+            signature_tx,
+            signature_rx,
+            transaction_tx,
+            transaction_rx,
+            call_tx,
+            call_rx,
+            parked_calls: Mutex::new(VecDeque::new()),
+            switch_chain_tx,
+            switch_chain_rx,
+            add_chain_tx,
+            add_chain_rx,
+            balance_tx,
+            balance_rx,
+            receipt_tx,
+            receipt_rx,
+            personal_signature_tx,
+            personal_signature_rx,
+            typed_signature_tx,
+            typed_signature_rx,
+            gas_estimate_tx,
+            gas_estimate_rx,
+            log_tx,
+            log_rx,
+            accounts_changed_tx,
+            accounts_changed_rx,
+            chain_changed_tx,
+            chain_changed_rx,
+            disconnected_tx,
+            disconnected_rx,
+            asset_watched_tx,
+            asset_watched_rx,
+            multicall_tx,
+            multicall_rx,
+            permissions_tx,
+            permissions_rx,
+            nonce_tx,
+            nonce_rx,
+            block_number_tx,
+            block_number_rx,
+            new_block_tx,
+            new_block_rx,
+            tx_details_tx,
+            tx_details_rx,
+            full_receipt_tx,
+            full_receipt_rx,
+            gas_price_tx,
+            gas_price_rx,
+            fee_history_tx,
+            fee_history_rx,
+            block_tx,
+            block_rx,
+            confirmed_tx,
+            confirmed_rx,
+            code_tx,
+            code_rx,
+            replaced_tx,
+            replaced_rx,
+            storage_tx,
+            storage_rx,
+            ens_address_tx,
+            ens_address_rx,
+            ens_name_tx,
+            ens_name_rx,
+            logs_tx,
+            logs_rx,
+            contract_call_tx,
+            contract_call_rx,
+            send_confirmed_tx,
+            send_confirmed_rx,
+            fees_tx,
+            fees_rx,
+            token_metadata_tx,
+            token_metadata_rx,
+            permit_tx,
+            permit_rx,
+            error_tx,
+            error_rx,
+        }
+    }
+
+    /// Whether an injected EIP-1193 provider (e.g. MetaMask) is present. When it
+    /// is missing, every operation reports `Web3Error::ProviderUnavailable`.
+    pub fn has_provider() -> bool {
+        TransportConfig::Eip1193.provider().is_some()
+    }
+
+    /// `Missing` when the configured transport has no provider behind it,
+    /// e.g. no wallet extension is installed.
+    pub fn provider_status(&self) -> ProviderStatus {
+        if self.is_provider_available() {
+            ProviderStatus::Available
+        } else {
+            ProviderStatus::Missing
+        }
+    }
+
+    /// Whether the configured transport could be set up, e.g. `false` when
+    /// there is no injected wallet to show a "please install MetaMask" screen.
+    pub fn is_provider_available(&self) -> bool {
+        self.transport.is_available()
+    }
+
+    /// Route connect/sign/send/call through the EIP-6963 wallet announced as
+    /// `rdns` (see `WalletProviders`) instead of the default injected one.
+    pub fn select_provider(&mut self, rdns: &str) {
+        self.transport = TransportConfig::Eip6963 {
+            rdns: rdns.to_owned(),
+        };
+    }
+
+    /// The EIP-6963 wallet chosen with `select_provider`, if any.
+    pub fn selected_provider(&self) -> Option<&str> {
+        match &self.transport {
+            TransportConfig::Eip6963 { rdns } => Some(rdns),
+            _ => None,
+        }
+    }
+
+    /// Transport for read-only requests, the `RpcConfig` override if any.
+    fn read_transport(&self) -> TransportConfig {
+        self.read_transport
+            .clone()
+            .unwrap_or_else(|| self.transport.clone())
+    }
+
+    fn fail(&self, op: WalletOp, error: impl Into<Web3Error>) {
+        let _ = self.error_tx.try_send(Web3OpError {
+            op,
+            id: None,
+            error: error.into(),
+        });
+    }
+
+    /// `fail` the request returned as `id`.
+    fn fail_request(&self, op: WalletOp, id: RequestId, error: impl Into<Web3Error>) {
+        let _ = self.error_tx.try_send(Web3OpError {
+            op,
+            id: Some(id),
+            error: error.into(),
+        });
+    }
+
+    /// Forget the connected accounts and chain, and drop pending results.
+    /// Requests still in flight complete, but their results (and errors) are
+    /// discarded. Subscriptions such as `watch_logs` keep running.
+    /// `WalletDisconnected` is fired on the next update.
+    pub fn disconnect(&mut self) {
+        self.session.fetch_add(1, Ordering::AcqRel);
+        self.accounts.clear();
+        self.chain_id = 0;
+
+        drain(&self.account_rx);
+        drain(&self.signature_rx);
+        drain(&self.transaction_rx);
+        drain(&self.call_rx);
+        self.parked_calls.lock().unwrap().clear();
+        drain(&self.switch_chain_rx);
+        drain(&self.add_chain_rx);
+        drain(&self.balance_rx);
+        drain(&self.receipt_rx);
+        drain(&self.personal_signature_rx);
+        drain(&self.typed_signature_rx);
+        drain(&self.gas_estimate_rx);
+        drain(&self.log_rx);
+        drain(&self.accounts_changed_rx);
+        drain(&self.chain_changed_rx);
+        drain(&self.asset_watched_rx);
+        drain(&self.multicall_rx);
+        drain(&self.permissions_rx);
+        drain(&self.nonce_rx);
+        drain(&self.block_number_rx);
+        drain(&self.new_block_rx);
+        drain(&self.tx_details_rx);
+        drain(&self.full_receipt_rx);
+        drain(&self.gas_price_rx);
+        drain(&self.fee_history_rx);
+        drain(&self.block_rx);
+        drain(&self.confirmed_rx);
+        drain(&self.code_rx);
+        drain(&self.replaced_rx);
+        drain(&self.storage_rx);
+        drain(&self.ens_address_rx);
+        drain(&self.ens_name_rx);
+        drain(&self.logs_rx);
+        drain(&self.contract_call_rx);
+        drain(&self.send_confirmed_rx);
+        drain(&self.fees_rx);
+        drain(&self.token_metadata_rx);
+        drain(&self.permit_rx);
+        drain(&self.error_rx);
+        self.sent.lock().unwrap().clear();
+
+        let _ = self.disconnected_tx.try_send(());
+    }
+
+    /// `disconnect`, and also `revoke_permissions` so the next `connect`
+    /// shows the wallet's account picker.
+    pub fn disconnect_and_revoke(&mut self) {
+        self.disconnect();
+        self.revoke_permissions();
+    }
+
+    fn next_id(&self) -> RequestId {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Run `task` with the transports of `WalletTasks`, failing with
+    /// `Web3Error::Timeout` after `OperationPolicy::timeout`.
+    fn spawn<T, F, Fut>(&self, op: WalletOp, tx: Sender<T>, task: F)
+    where
+        T: Send + 'static,
+        F: FnOnce(Transports) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<T>, Web3Error>> + 'static,
+    {
+        self.spawn_within(op, self.policy.timeout, tx, task);
+    }
+
+    /// `spawn` bounded by `timeout` instead of `OperationPolicy::timeout`.
+    fn spawn_within<T, F, Fut>(&self, op: WalletOp, timeout: Duration, tx: Sender<T>, task: F)
+    where
+        T: Send + 'static,
+        F: FnOnce(Transports) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<T>, Web3Error>> + 'static,
+    {
+        self.detach(op, None, tx, move |transports| {
+            timed(timeout, task(transports))
+        });
+    }
+
+    /// `spawn` the request returned as `id`, which its error carries too.
+    fn spawn_request<T, F, Fut>(&self, op: WalletOp, id: RequestId, tx: Sender<T>, task: F)
+    where
+        T: Send + 'static,
+        F: FnOnce(Transports) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<T>, Web3Error>> + 'static,
+    {
+        let timeout = self.policy.timeout;
+        self.detach(op, Some(id), tx, move |transports| {
+            timed(timeout, task(transports))
+        });
+    }
+
+    /// `spawn` a read with `read_transport`, see `retrying`.
+    fn spawn_read<T, F, Fut>(&self, op: WalletOp, tx: Sender<T>, task: F)
+    where
+        T: Send + 'static,
+        F: Fn(Web3<WalletTransport>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<T>, Web3Error>> + 'static,
+    {
+        self.detach(op, None, tx, self.retrying(task));
+    }
+
+    /// `spawn` without the timeout, for watchers which run until they are done
+    /// or their receiver is dropped.
+    fn spawn_untimed<T, F, Fut>(&self, op: WalletOp, tx: Sender<T>, task: F)
+    where
+        T: Send + 'static,
+        F: FnOnce(Transports) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<T>, Web3Error>> + 'static,
+    {
+        self.detach(op, None, tx, task);
+    }
+
+    /// Call `task` with `read_transport`, and again for each of the
+    /// `OperationPolicy::retries` when it times out or the transport fails.
+    fn retrying<T, F, Fut>(
+        &self,
+        task: F,
+    ) -> impl FnOnce(Transports) -> LocalBoxFuture<'static, Result<T, Web3Error>> + Send
+    where
+        T: 'static,
+        F: Fn(Web3<WalletTransport>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Web3Error>> + 'static,
+    {
+        let transport = self.read_transport();
+        let policy = self.policy;
+        move |transports| {
+            let web3 = transports.web3(&transport);
+            Box::pin(async move {
+                let web3 = web3?;
+                let mut backoff = RETRY_BACKOFF;
+                let mut retries = policy.retries;
+                loop {
+                    match timed(policy.timeout, task(web3.clone())).await {
+                        Err(e) if retries > 0 && e.is_transient() => {
+                            retries -= 1;
+                            Delay::new(backoff).await;
+                            backoff *= 2;
+                        }
+                        result => return result,
+                    }
+                }
+            })
+        }
+    }
+
+    /// Queue `task` for `WalletTasks::run`, which starts it on the thread
+    /// owning the transports. Its result goes to `tx`, its error to `recv_error`.
+    fn detach<T, F, Fut>(&self, op: WalletOp, id: Option<RequestId>, tx: Sender<T>, task: F)
+    where
+        T: Send + 'static,
+        F: FnOnce(Transports) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<T>, Web3Error>> + 'static,
+    {
+        let error_tx = self.error_tx.clone();
+        let session = self.session.clone();
+        let started = session.load(Ordering::Acquire);
+        let start = move |transports| -> LocalBoxFuture<'static, ()> {
+            let task = task(transports);
+            Box::pin(async move {
+                let result = task.await;
+                // the wallet was disconnected meanwhile, drop the stale result
+                if session.load(Ordering::Acquire) != started {
+                    return;
+                }
+                match result {
+                    Ok(Some(value)) => {
+                        let _ = tx.send(value).await;
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        let _ = error_tx.send(Web3OpError { op, id, error }).await;
+                    }
+                }
+            })
+        };
+        let _ = self.tasks_tx.try_send(WalletTask {
+            op,
+            id,
+            start: Box::new(start),
+        });
+    }
+
+    pub fn connect(&self) {
+        #[cfg(feature = "http")]
+        if let Some(key) = self.transport.local_key() {
+            return self.connect_local(key);
+        }
+
+        let transport = self.transport.clone();
+        self.spawn(
+            WalletOp::Connect,
+            self.account_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let addrs = web3.eth().request_accounts().await?;
+                let chain = web3.eth().chain_id().await?;
+
+                if addrs.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some((addrs, chain.as_u64())))
+                }
+            },
+        );
+        self.watch_changes();
+    }
+
+    /// Restore the session of an already authorized wallet, e.g. after a page
+    /// reload. Unlike `connect` (`eth_requestAccounts`) this uses `eth_accounts`,
+    /// which never prompts: if the wallet has not authorized the site nothing
+    /// is reported and the state is left untouched.
+    pub fn silent_connect(&self) {
+        #[cfg(feature = "http")]
+        if let Some(key) = self.transport.local_key() {
+            return self.connect_local(key);
+        }
+
+        let transport = self.transport.clone();
+        self.spawn(
+            WalletOp::Connect,
+            self.account_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let addrs = web3.eth().accounts().await?;
+                if addrs.is_empty() {
+                    return Ok(None);
+                }
+                let chain = web3.eth().chain_id().await?;
+                Ok(Some((addrs, chain.as_u64())))
+            },
+        );
+        self.watch_changes();
+    }
+
+    /// Forward the wallet's `accountsChanged` and `chainChanged` notifications,
+    /// once per transport. Only EIP-1193 providers send them.
+    fn watch_changes(&self) {
+        let mut watched = self.watched.lock().unwrap();
+        if watched.as_ref() == Some(&self.transport) {
+            return;
+        }
+        if self.transport.provider().is_none() {
+            return;
+        }
+        *watched = Some(self.transport.clone());
+
+        let transport = self.transport.clone();
+        let tx = self.accounts_changed_tx.clone();
+        self.spawn_untimed(
+            WalletOp::Connect,
+            tx.clone(),
+            move |transports| async move {
+                let Some(eip1193) = transports.web3(&transport)?.transport().eip1193() else {
+                    return Ok(None);
+                };
+                let mut accounts = Box::pin(eip1193.accounts_changed_stream());
+                while let Some(addrs) = accounts.next().await {
+                    if tx.send(addrs).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None)
+            },
+        );
+
+        let transport = self.transport.clone();
+        let tx = self.chain_changed_tx.clone();
+        self.spawn_untimed(
+            WalletOp::Connect,
+            tx.clone(),
+            move |transports| async move {
+                let Some(eip1193) = transports.web3(&transport)?.transport().eip1193() else {
+                    return Ok(None);
+                };
+                let mut chains = Box::pin(eip1193.chain_changed_stream());
+                while let Some(chain) = chains.next().await {
+                    if tx.send(chain.as_u64()).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None)
+            },
+        );
+    }
+
+    /// Same as `sign_addr`, an invalid `account` is reported through `recv_error`.
+    pub fn sign(&self, account: &str, msg: String) -> RequestId {
+        match account.parse() {
+            Ok(account) => self.sign_addr(account, msg.into_bytes()),
+            Err(e) => {
+                let id = self.next_id();
+                self.fail_request(WalletOp::Sign, id, e);
+                id
+            }
+        }
+    }
+
+    /// Sign `msg` with `eth_sign`. It is deprecated and many wallets warn about
+    /// or refuse it, prefer `personal_sign`.
+    pub fn sign_addr(&self, account: H160, msg: Vec<u8>) -> RequestId {
+        let id = self.next_id();
+        #[cfg(feature = "http")]
+        if let Some(key) = self.transport.local_key() {
+            let tx = self.signature_tx.clone();
+            self.sign_local(key, WalletOp::Sign, id, tx, msg);
+            return id;
+        }
+
+        let transport = self.transport.clone();
+        let tx = self.signature_tx.clone();
+        self.spawn_request(WalletOp::Sign, id, tx, move |transports| async move {
+            let web3 = transports.web3(&transport)?;
+            Ok(Some((id, web3.eth().sign(account, msg.into()).await?)))
+        });
+        id
+    }
+
+    /// Same as `send_addr`, an invalid `from` is reported through `recv_error`.
+    pub fn send(&self, from: &str, to: H160, data: Vec<u8>) -> RequestId {
+        match from.parse() {
+            Ok(from) => self.send_addr(from, to, data),
+            Err(e) => {
+                let id = self.next_id();
+                self.fail_request(WalletOp::Send, id, e);
+                id
+            }
+        }
+    }
+
+    /// Sign `msg` with `personal_sign`, which prefixes it with
+    /// `\x19Ethereum Signed Message:\n<len>` before hashing.
+    pub fn personal_sign(&self, account: H160, msg: String) -> RequestId {
+        self.personal_sign_bytes(account, msg.into_bytes())
+    }
+
+    /// Same as `personal_sign`, with `msg` given as `0x`-prefixed hex data.
+    pub fn personal_sign_hex(&self, account: H160, msg: &str) -> RequestId {
+        match msg.trim_start_matches("0x").from_hex() {
+            Ok(bytes) => self.personal_sign_bytes(account, bytes),
+            Err(e) => {
+                let id = self.next_id();
+                self.fail_request(WalletOp::PersonalSign, id, e);
+                id
+            }
+        }
+    }
+
+    /// Same as `personal_sign`, for binary messages.
+    pub fn personal_sign_bytes(&self, account: H160, msg: Vec<u8>) -> RequestId {
+        let id = self.next_id();
+        let tx = self.personal_signature_tx.clone();
+        #[cfg(feature = "http")]
+        if let Some(key) = self.transport.local_key() {
+            self.sign_local(key, WalletOp::PersonalSign, id, tx, msg);
+            return id;
+        }
+
+        let transport = self.transport.clone();
+        self.spawn_request(
+            WalletOp::PersonalSign,
+            id,
+            tx,
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let params = vec![serde_json::to_value(Bytes(msg))?, json!(account)];
+                let signature = web3.transport().execute("personal_sign", params).await?;
+                Ok(Some((id, serde_json::from_value(signature)?)))
+            },
+        );
+        id
+    }
+
+    /// Sign EIP-712 typed data with `eth_signTypedData_v4`.
+    pub fn sign_typed_data(&self, account: H160, typed_data: TypedData) -> RequestId {
+        let id = self.next_id();
+        let transport = self.transport.clone();
+        let tx = self.typed_signature_tx.clone();
+        self.spawn_request(
+            WalletOp::SignTypedData,
+            id,
+            tx,
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let params = vec![json!(account), json!(typed_data.to_json()?)];
+                let signature = web3
+                    .transport()
+                    .execute("eth_signTypedData_v4", params)
+                    .await?;
+                Ok(Some((id, serde_json::from_value(signature)?)))
+            },
+        );
+        id
+    }
+
+    /// `sign_typed_data` with the typed data as an EIP-712 JSON document.
+    pub fn sign_typed_data_json(&self, account: H160, typed_data_json: String) -> RequestId {
+        match TypedData::from_json(&typed_data_json) {
+            Ok(typed_data) => self.sign_typed_data(account, typed_data),
+            Err(e) => {
+                let id = self.next_id();
+                self.fail_request(WalletOp::SignTypedData, id, e);
+                id
+            }
+        }
+    }
+
+    pub fn send_addr(&self, from: H160, to: H160, data: Vec<u8>) -> RequestId {
+        self.send_with(from, to, data, SendOptions::default())
+    }
+
+    /// Send a transaction with value, gas and fee overrides.
+    pub fn send_with(
+        &self,
+        from: H160,
+        to: H160,
+        data: Vec<u8>,
+        options: SendOptions,
+    ) -> RequestId {
+        self.send_transaction(from, Some(to), data, options)
+    }
+
+    /// Send a transaction calling `method` of `contract` with `args` encoded
+    /// against its ABI, delivered like `send`. An unknown method or mismatched
+    /// args fail before anything is sent.
+    pub fn send_contract(
+        &self,
+        from: H160,
+        contract: &Contract,
+        method: &str,
+        args: &[Token],
+        options: SendOptions,
+    ) -> RequestId {
+        match contract.encode(method, args) {
+            Ok(data) => self.send_with(from, contract.address, data, options),
+            Err(e) => {
+                let id = self.next_id();
+                self.fail_request(WalletOp::Send, id, e);
+                id
+            }
+        }
+    }
+
+    /// Send `value` wei along with the transaction, e.g. to a payable function.
+    /// `None` sends nothing.
+    pub fn send_value(
+        &self,
+        from: H160,
+        to: H160,
+        data: Vec<u8>,
+        value: Option<U256>,
+    ) -> RequestId {
+        let options = SendOptions {
+            value: Some(value.unwrap_or_default()),
+            ..Default::default()
+        };
+        self.send_with(from, to, data, options)
+    }
+
+    /// Send an EIP-1559 transaction with the given fee caps.
+    pub fn send_1559(
+        &self,
+        from: H160,
+        to: H160,
+        data: Vec<u8>,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    ) -> RequestId {
+        let options = SendOptions {
+            max_fee_per_gas: Some(max_fee_per_gas),
+            max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
+            ..Default::default()
+        };
+        self.send_with(from, to, data, options)
+    }
+
+    /// Broadcast a transaction signed elsewhere (e.g. by a relayer), delivered
+    /// like `send`.
+    pub fn send_raw_transaction(&self, raw: Vec<u8>) -> RequestId {
+        let id = self.next_id();
+        let transport = self.transport.clone();
+        self.spawn_request(
+            WalletOp::Send,
+            id,
+            self.transaction_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let hash = web3.eth().send_raw_transaction(Bytes(raw)).await?;
+                Ok(Some((id, hash)))
+            },
+        );
+        id
+    }
+
+    /// `send_with`, then wait for the transaction as `watch_transaction` does
+    /// with `watch`. The hash is delivered like `send` once submitted, then
+    /// both with the receipt through `recv_send_confirmed` and
+    /// `ConfirmedTransaction`.
+    pub fn send_and_confirm(
+        &self,
+        from: H160,
+        to: H160,
+        data: Vec<u8>,
+        options: SendOptions,
+        watch: WatchOptions,
+    ) -> RequestId {
+        let id = self.next_id();
+        let read = self.read_transport();
+        let submit = self.submit(from, Some(to), data, options);
+        let transaction_tx = self.transaction_tx.clone();
+        let timeout = self.policy.timeout;
+        let tx = self.send_confirmed_tx.clone();
+        self.detach(WalletOp::Send, Some(id), tx, move |transports| async move {
+            let web3 = transports.web3(&read)?;
+            let hash = timed(timeout, submit.send(transports)).await?;
+            let _ = transaction_tx.send((id, hash)).await;
+            let receipt = poll_receipt(&web3, hash, watch).await?;
+            Ok(Some((id, hash, receipt.into())))
+        });
+        id
+    }
+
+    /// Deploy a contract, `data` being its bytecode followed by the constructor
+    /// arguments (see `Contract::encode_deploy`). Delivered like `send`,
+    /// the address is in the receipt's `contract_address`, see `watch_transaction`.
+    pub fn deploy(&self, from: H160, data: Vec<u8>) -> RequestId {
+        self.send_transaction(from, None, data, SendOptions::default())
+    }
+
+    /// `deploy` with the constructor arguments encoded against the ABI of
+    /// `contract`, whose address is ignored.
+    pub fn deploy_contract(
+        &self,
+        from: H160,
+        contract: &Contract,
+        bytecode: &[u8],
+        tokens: &[Token],
+    ) -> RequestId {
+        match contract.encode_deploy(bytecode, tokens) {
+            Ok(data) => self.deploy(from, data),
+            Err(e) => {
+                let id = self.next_id();
+                self.fail_request(WalletOp::Send, id, e);
+                id
+            }
+        }
+    }
+
+    fn send_transaction(
+        &self,
+        from: H160,
+        to: Option<H160>,
+        data: Vec<u8>,
+        options: SendOptions,
+    ) -> RequestId {
+        let id = self.next_id();
+        let submit = self.submit(from, to, data, options);
+        self.spawn_request(
+            WalletOp::Send,
+            id,
+            self.transaction_tx.clone(),
+            move |transports| async move { Ok(Some((id, submit.send(transports).await?))) },
+        );
+        id
+    }
+
+    /// The transaction to send, see `send_recorded`.
+    fn submit(&self, from: H160, to: Option<H160>, data: Vec<u8>, options: SendOptions) -> Submit {
+        Submit {
+            transport: self.transport.clone(),
+            sent: self.sent.clone(),
+            from,
+            to,
+            data,
+            options,
+        }
+    }
+
+    /// The transactions sent in this session as they were sent: the nonce
+    /// always, the gas and fees only if set, otherwise the wallet picked them.
+    pub fn sent_transactions(&self) -> Vec<Transaction> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Speed up the transaction sent in this session with `original_nonce` by
+    /// sending it again with the same nonce. Fees unset in `options` are the
+    /// original ones raised by 10%, the minimum nodes accept for a replacement.
+    /// Fees the wallet picked are looked up on the node first.
+    /// The new hash is delivered like `send`, then `TransactionReplaced` tells
+    /// which of the two was mined.
+    pub fn replace_transaction(&self, original_nonce: U256, options: SendOptions) -> RequestId {
+        self.resend(original_nonce, options, false)
+    }
+
+    /// Like `replace_transaction`, but replace the transaction with an empty
+    /// self-send so it does nothing if the replacement is mined.
+    pub fn cancel_transaction(&self, original_nonce: U256, options: SendOptions) -> RequestId {
+        self.resend(original_nonce, options, true)
+    }
+
+    fn resend(&self, nonce: U256, options: SendOptions, cancel: bool) -> RequestId {
+        let id = self.next_id();
+        let original = self
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|tx| tx.nonce == nonce)
+            .cloned();
+        let Some(original) = original else {
+            self.fail_request(
+                WalletOp::Replace,
+                id,
+                Web3Error::InvalidParams(format!("no transaction sent with nonce {}", nonce)),
+            );
+            return id;
+        };
+        let Some(from) = original.from else {
+            self.fail_request(
+                WalletOp::Replace,
+                id,
+                Web3Error::InvalidParams("original transaction has no sender".to_owned()),
+            );
+            return id;
+        };
+
+        let read = self.read_transport();
+        let transport = self.transport.clone();
+        let sent = self.sent.clone();
+        let transaction_tx = self.transaction_tx.clone();
+        let watch = self.watch_options;
+        let timeout = self.policy.timeout;
+        let tx = self.replaced_tx.clone();
+        self.detach(
+            WalletOp::Replace,
+            Some(id),
+            tx,
+            move |transports| async move {
+                let web3 = transports.web3(&read)?;
+                let mut original = original;
+                let fees_unknown =
+                    original.gas_price.is_none() && original.max_fee_per_gas.is_none();
+                if fees_unknown && options.gas_price.is_none() && !options.is_eip1559() {
+                    // the wallet picked them, the node knows the transaction by now
+                    let id = TransactionId::Hash(original.hash);
+                    if let Some(found) = web3.eth().transaction(id).await? {
+                        original = found;
+                    } else {
+                        original.gas_price = Some(web3.eth().gas_price().await?);
+                    }
+                }
+
+                let (to, data, options) = replacement(&original, from, options, cancel);
+                let submit = send_recorded(&transports, transport, sent, from, to, data, options);
+                let hash = timed(timeout, submit).await?;
+                let _ = transaction_tx.send((id, hash)).await;
+
+                for _ in 0..watch.max_attempts {
+                    for (candidate, replaced) in [(hash, true), (original.hash, false)] {
+                        if web3.eth().transaction_receipt(candidate).await?.is_some() {
+                            return Ok(Some(TransactionReplaced {
+                                nonce,
+                                hash: candidate,
+                                replaced,
+                            }));
+                        }
+                    }
+                    Delay::new(watch.interval).await;
+                }
+                Err(Web3Error::Timeout)
+            },
+        );
+        id
+    }
+
+    pub fn call(&self, to: H160, method: String, data: Vec<u8>) -> RequestId {
+        self.call_block(to, method, data, None)
+    }
+
+    /// `call` against the state at `block`, a `BlockNumber` or a hash, e.g. to
+    /// read several values that must agree with each other, or historical
+    /// state like a leaderboard snapshot.
+    pub fn call_at(
+        &self,
+        to: H160,
+        method: String,
+        data: Vec<u8>,
+        block: impl Into<BlockId>,
+    ) -> RequestId {
+        self.call_block(to, method, data, Some(block.into()))
+    }
+
+    fn call_block(
+        &self,
+        to: H160,
+        method: String,
+        data: Vec<u8>,
+        block: Option<BlockId>,
+    ) -> RequestId {
+        let id = self.next_id();
+        let call = CallRequest {
+            to: Some(to),
+            data: Some(data.into()),
+            ..Default::default()
+        };
+        let task = self.retrying(move |web3| {
+            let (call, method) = (call.clone(), method.clone());
+            async move {
+                let bytes = web3.eth().call(call, block).await?;
+                Ok(Some((id, method, bytes.0)))
+            }
+        });
+        self.detach(WalletOp::Call, Some(id), self.call_tx.clone(), task);
+        id
+    }
+
+    /// Encode `method` with `args`, call it and decode the result against the
+    /// ABI of `contract`, delivered through `recv_contract_call` and
+    /// `ContractCallReturned`.
+    pub fn call_contract(&self, contract: &Contract, method: &str, args: &[Token]) -> RequestId {
+        let id = self.next_id();
+        let data = match contract.encode(method, args) {
+            Ok(data) => data,
+            Err(e) => {
+                self.fail_request(WalletOp::Call, id, e);
+                return id;
+            }
+        };
+        let call = CallRequest {
+            to: Some(contract.address),
+            data: Some(data.into()),
+            ..Default::default()
+        };
+        let contract = contract.clone();
+        let method = method.to_owned();
+        let task = self.retrying(move |web3| {
+            let (call, contract, method) = (call.clone(), contract.clone(), method.clone());
+            async move {
+                let bytes = web3.eth().call(call, None).await?;
+                let tokens = contract.decode(&method, &bytes.0)?;
+                Ok(Some((id, method, tokens)))
+            }
+        });
+        self.detach(
+            WalletOp::Call,
+            Some(id),
+            self.contract_call_tx.clone(),
+            task,
+        );
+        id
+    }
+
+    /// `multicall` through the Multicall3 deployment of `WalletConfig`.
+    pub fn batch_call(&self, calls: Vec<(H160, Vec<u8>)>) {
+        self.multicall(self.multicall_address, calls);
+    }
+
+    /// Batch read-only `calls` (target, call data) in one request through the
+    /// Multicall3 contract at `multicall`, usually `MULTICALL3`. A failing call
+    /// doesn't fail the batch, its result is `(false, revert data)`. An empty
+    /// batch is answered without a request.
+    pub fn multicall(&self, multicall: H160, calls: Vec<(H160, Vec<u8>)>) {
+        if calls.is_empty() {
+            let _ = self.multicall_tx.try_send(vec![]);
+            return;
+        }
+        let call = CallRequest {
+            to: Some(multicall),
+            data: Some(multicall::encode(calls).into()),
+            ..Default::default()
+        };
+        self.spawn_read(
+            WalletOp::Multicall,
+            self.multicall_tx.clone(),
+            move |web3| {
+                let call = call.clone();
+                async move {
+                    let bytes = web3.eth().call(call, None).await?;
+                    Ok(Some(multicall::decode(&bytes.0)?))
+                }
+            },
+        );
+    }
+
+    /// Ask for the `eth_accounts` permission again (EIP-2255), which shows the
+    /// wallet's account picker even if an account was approved before.
+    pub fn request_permissions(&self) {
+        self.permissions("wallet_requestPermissions");
+    }
+
+    /// Give up the `eth_accounts` permission, the next `connect` asks the user
+    /// to pick an account again. Not every wallet supports it.
+    pub fn revoke_permissions(&self) {
+        self.permissions("wallet_revokePermissions");
+    }
+
+    fn permissions(&self, method: &'static str) {
+        let transport = self.transport.clone();
+        self.spawn(
+            WalletOp::Permissions,
+            self.permissions_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let params = vec![json!({ "eth_accounts": {} })];
+                web3.transport().execute(method, params).await?;
+                let granted = web3
+                    .transport()
+                    .execute("wallet_getPermissions", vec![])
+                    .await?;
+                let names = granted
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|p| p["parentCapability"].as_str())
+                    .map(str::to_owned)
+                    .collect();
+                Ok(Some(names))
+            },
+        );
+    }
+
+    /// Ask the wallet to switch to `chain_id` (EIP-3326). If the wallet doesn't
+    /// know the chain, the error reported by `recv_error` is `is_unrecognized_chain`.
+    /// Already being on `chain_id` succeeds right away, without a wallet prompt.
+    pub fn switch_chain(&self, chain_id: u64) {
+        if self.chain_id == chain_id {
+            let _ = self.switch_chain_tx.try_send(chain_id);
+            return;
+        }
+
+        let transport = self.transport.clone();
+        self.spawn(
+            WalletOp::SwitchChain,
+            self.switch_chain_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let params = json!({ "chainId": format!("{:#x}", chain_id) });
+                web3.transport()
+                    .execute("wallet_switchEthereumChain", vec![params])
+                    .await?;
+                Ok(Some(chain_id))
+            },
+        );
+    }
+
+    /// Ask the wallet to add a new chain (EIP-3085).
+    pub fn add_chain(&self, params: AddChainParams) {
+        if let Err(message) = params.validate() {
+            return self.fail(WalletOp::AddChain, Web3Error::InvalidParams(message));
+        }
+
+        let transport = self.transport.clone();
+        let tx = self.add_chain_tx.clone();
+        self.spawn(WalletOp::AddChain, tx, move |transports| async move {
+            let web3 = transports.web3(&transport)?;
+            let chain_id = params.chain_id;
+            web3.transport()
+                .execute(
+                    "wallet_addEthereumChain",
+                    vec![serde_json::to_value(params)?],
+                )
+                .await?;
+            Ok(Some(chain_id))
+        });
+    }
+
+    /// Switch to the chain of `params`, adding it to the wallet first if it
+    /// doesn't know it. Delivered like `switch_chain`.
+    pub fn switch_or_add_chain(&self, params: AddChainParams) {
+        if let Err(message) = params.validate() {
+            return self.fail(WalletOp::SwitchChain, Web3Error::InvalidParams(message));
+        }
+
+        let transport = self.transport.clone();
+        self.spawn(
+            WalletOp::SwitchChain,
+            self.switch_chain_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let chain_id = params.chain_id;
+                let switch = json!({ "chainId": format!("{:#x}", chain_id) });
+                let switched = web3
+                    .transport()
+                    .execute("wallet_switchEthereumChain", vec![switch.clone()])
+                    .await;
+                match switched.map_err(Web3Error::from) {
+                    Ok(_) => {}
+                    Err(Web3Error::UnrecognizedChain) => {
+                        web3.transport()
+                            .execute(
+                                "wallet_addEthereumChain",
+                                vec![serde_json::to_value(params)?],
+                            )
+                            .await?;
+                        web3.transport()
+                            .execute("wallet_switchEthereumChain", vec![switch])
+                            .await?;
+                    }
+                    Err(e) => return Err(e),
+                }
+                Ok(Some(chain_id))
+            },
+        );
+    }
+
+    /// Ask the wallet to display a token (EIP-747), the result is whether the
+    /// user accepted.
+    pub fn watch_asset(&self, asset: WatchAssetParams) {
+        let transport = self.transport.clone();
+        self.spawn(
+            WalletOp::WatchAsset,
+            self.asset_watched_tx.clone(),
+            move |transports| async move {
+                let params = serde_json::to_value(asset)?;
+                // wallets expect the params as an object, not in an array
+                let added = match transport.provider() {
+                    Some(provider) => {
+                        transport::request_with_object(provider, "wallet_watchAsset", params)
+                            .await?
+                    }
+                    None => {
+                        transports
+                            .web3(&transport)?
+                            .transport()
+                            .execute("wallet_watchAsset", vec![params])
+                            .await?
+                    }
+                };
+                Ok(Some(added.as_bool().unwrap_or(false)))
+            },
+        );
+    }
+
+    /// Fetch the current gas price, for legacy transactions.
+    pub fn gas_price(&self) {
+        self.spawn_read(
+            WalletOp::GasPrice,
+            self.gas_price_tx.clone(),
+            |web3| async move { Ok(Some(web3.eth().gas_price().await?)) },
+        );
+    }
+
+    /// Fetch the base fees and the priority fee `reward_percentiles` of the
+    /// last `block_count` blocks, to pick EIP-1559 fees for `send_1559`.
+    pub fn fee_history(&self, block_count: u64, reward_percentiles: Vec<f64>) {
+        self.spawn_read(
+            WalletOp::FeeHistory,
+            self.fee_history_tx.clone(),
+            move |web3| {
+                let reward_percentiles = reward_percentiles.clone();
+                async move {
+                    let history = web3
+                        .eth()
+                        .fee_history(
+                            block_count.into(),
+                            BlockNumber::Latest,
+                            Some(reward_percentiles),
+                        )
+                        .await?;
+                    Ok(Some(history))
+                }
+            },
+        );
+    }
+
+    /// Suggest EIP-1559 fees from the last blocks: the next base fee and the
+    /// median priority fee paid, delivered through `recv_fees` and `FeesSuggested`.
+    pub fn suggest_fees(&self) {
+        self.spawn_read(
+            WalletOp::SuggestFees,
+            self.fees_tx.clone(),
+            |web3| async move {
+                let history = web3
+                    .eth()
+                    .fee_history(10.into(), BlockNumber::Latest, Some(vec![50.0]))
+                    .await?;
+                Ok(Some(suggest_fees(history)?))
+            },
+        );
+    }
+
+    /// Fetch the transaction count of `account`, including its pending
+    /// transactions if `pending`, i.e. the nonce of its next transaction.
+    pub fn get_nonce(&self, account: H160, pending: bool) {
+        let block = if pending {
+            BlockNumber::Pending
+        } else {
+            BlockNumber::Latest
+        };
+        self.spawn_read(
+            WalletOp::Nonce,
+            self.nonce_tx.clone(),
+            move |web3| async move {
+                let nonce = web3.eth().transaction_count(account, Some(block)).await?;
+                Ok(Some(nonce))
+            },
+        );
+    }
+
+    /// `balance` of `account` at the latest block, delivered through `recv_balance`.
+    pub fn get_balance(&self, account: H160) {
+        self.balance(account, None);
+    }
+
+    /// Fetch the native token balance of `account`, at `block` or the latest block.
+    pub fn balance(&self, account: H160, block: Option<BlockNumber>) {
+        self.spawn_read(
+            WalletOp::Balance,
+            self.balance_tx.clone(),
+            move |web3| async move { Ok(Some(web3.eth().balance(account, block).await?)) },
+        );
+    }
+
+    /// `watch_transaction` with the poll interval of `WalletConfig`.
+    pub fn wait_for_receipt(&self, hash: H256) {
+        self.watch_transaction(hash, self.watch_options);
+    }
+
+    /// Poll for the receipt of `hash` until it is mined, delivered through
+    /// `recv_receipt` and `TransactionMined`. With `options.confirmations` it
+    /// is delivered through `recv_confirmed` and `TransactionConfirmed` once
+    /// enough blocks are mined on top, and `Web3Error::Reorged` is reported if
+    /// the transaction leaves its block meanwhile. If it is not done after
+    /// `max_attempts` polls, `Web3Error::Timeout` is reported.
+    pub fn watch_transaction(&self, hash: H256, options: WatchOptions) {
+        let read = self.read_transport();
+        let tx = if options.confirmations == 0 {
+            self.receipt_tx.clone()
+        } else {
+            self.confirmed_tx.clone()
+        };
+        self.spawn_untimed(
+            WalletOp::WatchTransaction,
+            tx,
+            move |transports| async move {
+                let web3 = transports.web3(&read)?;
+                Ok(Some(poll_receipt(&web3, hash, options).await?.into()))
+            },
+        );
+    }
+
+    /// `watch_confirmations` with the poll interval of `WalletConfig`.
+    pub fn wait_for_confirmations(&self, hash: H256, confirmations: u64) {
+        self.watch_confirmations(hash, confirmations, self.watch_options);
+    }
+
+    /// `watch_transaction` waiting for `confirmations` blocks on top of the
+    /// one including the transaction.
+    pub fn watch_confirmations(&self, hash: H256, confirmations: u64, options: WatchOptions) {
+        self.watch_transaction(
+            hash,
+            WatchOptions {
+                confirmations,
+                ..options
+            },
+        );
+    }
+
+    /// Fetch a transaction as returned by the node, `None` if it is unknown.
+    pub fn get_transaction(&self, hash: H256) {
+        self.spawn_read(
+            WalletOp::GetTransaction,
+            self.tx_details_tx.clone(),
+            move |web3| async move {
+                let id = TransactionId::Hash(hash);
+                Ok(Some(web3.eth().transaction(id).await?))
+            },
+        );
+    }
+
+    /// Fetch the full receipt of a transaction, including its logs. `None`
+    /// while it is pending, see `watch_transaction` to wait for it.
+    pub fn get_receipt(&self, hash: H256) {
+        self.spawn_read(
+            WalletOp::GetReceipt,
+            self.full_receipt_tx.clone(),
+            move |web3| async move { Ok(Some(web3.eth().transaction_receipt(hash).await?)) },
+        );
+    }
+
+    /// Fetch the code deployed at `address`, empty if it is not a contract.
+    pub fn get_code(&self, address: H160) {
+        self.spawn_read(
+            WalletOp::GetCode,
+            self.code_tx.clone(),
+            move |web3| async move {
+                let code = web3.eth().code(address, None).await?;
+                Ok(Some((address, code.0)))
+            },
+        );
+    }
+
+    /// Read the raw storage `slot` of `address` at `block`, the latest if
+    /// `None`. E.g. the EIP-1967 implementation slot of a proxy.
+    pub fn get_storage_at(&self, address: H160, slot: U256, block: Option<BlockNumber>) {
+        self.spawn_read(
+            WalletOp::GetStorage,
+            self.storage_tx.clone(),
+            move |web3| async move {
+                let value = web3.eth().storage(address, slot, block).await?;
+                Ok(Some((address, slot, value)))
+            },
+        );
+    }
+
+    /// Fetch a block by number or hash, without its transactions. `None` if
+    /// it does not exist yet.
+    pub fn get_block(&self, id: BlockId) {
+        self.spawn_read(
+            WalletOp::GetBlock,
+            self.block_tx.clone(),
+            move |web3| async move { Ok(Some(web3.eth().block(id).await?)) },
+        );
+    }
+
+    /// Estimate the gas of a transaction. If it would revert, the error is
+    /// `Web3Error::Reverted` with the reason when the node returns one.
+    pub fn estimate_gas(&self, from: H160, to: H160, data: Vec<u8>, value: Option<U256>) {
+        self.estimate(from, Some(to), data, value);
+    }
+
+    /// Estimate the gas of a `deploy`.
+    pub fn estimate_deploy_gas(&self, from: H160, data: Vec<u8>) {
+        self.estimate(from, None, data, None);
+    }
+
+    fn estimate(&self, from: H160, to: Option<H160>, data: Vec<u8>, value: Option<U256>) {
+        let req = CallRequest {
+            from: Some(from),
+            to,
+            data: Some(data.into()),
+            value,
+            ..Default::default()
+        };
+        self.spawn_read(
+            WalletOp::EstimateGas,
+            self.gas_estimate_tx.clone(),
+            move |web3| {
+                let req = req.clone();
+                async move { Ok(Some(web3.eth().estimate_gas(req, None).await?)) }
+            },
+        );
+    }
+
+    /// Fetch the number of the latest block.
+    pub fn block_number(&self) {
+        self.spawn_read(
+            WalletOp::BlockNumber,
+            self.block_number_tx.clone(),
+            |web3| async move {
+                let number = web3.eth().block_number().await?;
+                Ok(Some(number.as_u64().into()))
+            },
+        );
+    }
+
+    /// Poll the chain head every `interval` and deliver each new block through
+    /// `recv_new_block` and `NewBlock`, in order and once. Blocks produced
+    /// between two polls are all reported.
+    pub fn watch_blocks(&self, interval: Duration) {
+        let read = self.read_transport();
+        let tx = self.new_block_tx.clone();
+        self.spawn_untimed(
+            WalletOp::WatchBlocks,
+            tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&read)?;
+                let mut next = None;
+                loop {
+                    let head = web3.eth().block_number().await?.as_u64();
+                    for number in next.unwrap_or(head)..=head {
+                        let id = BlockId::Number(BlockNumber::Number(number.into()));
+                        let Some(block) = web3.eth().block(id).await? else {
+                            break;
+                        };
+                        let block = NewBlock {
+                            number,
+                            hash: block.hash.unwrap_or_default(),
+                            timestamp: block.timestamp.as_u64(),
+                        };
+                        if tx.send(block).await.is_err() {
+                            return Ok(None);
+                        }
+                        next = Some(number + 1);
+                    }
+                    Delay::new(interval).await;
+                }
+            },
+        );
+    }
+
+    /// Query past logs matching `filter`, delivered through `recv_logs` and
+    /// `LogsFetched` in chunks of `LOG_BLOCK_RANGE` blocks as they arrive.
+    pub fn get_logs(&self, filter: LogFilter) {
+        let read = self.read_transport();
+        let logs_tx = self.logs_tx.clone();
+        self.spawn_untimed(
+            WalletOp::GetLogs,
+            logs_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&read)?;
+                let to = match filter.to_block {
+                    Some(to) => to,
+                    None => web3.eth().block_number().await?.as_u64(),
+                };
+                let mut from = filter.from_block;
+                loop {
+                    let end = to.min(from.saturating_add(LOG_BLOCK_RANGE - 1));
+                    let logs = web3.eth().logs(filter.filter(from, end)).await?;
+                    if end >= to {
+                        return Ok(Some((logs, true)));
+                    }
+                    if logs_tx.send((logs, false)).await.is_err() {
+                        return Ok(None);
+                    }
+                    from = end + 1;
+                }
+            },
+        );
+    }
+
+    /// Poll `eth_getLogs` every `interval` for logs of `address` from block
+    /// `from_block` onwards, each log is delivered once through `recv_log`.
+    /// `topics` filters on the first topic (the event signature, see
+    /// `Contract::event_signature`), empty matches all logs.
+    pub fn watch_logs(
+        &self,
+        address: H160,
+        topics: Vec<H256>,
+        from_block: u64,
+        interval: Duration,
+    ) {
+        let read = self.read_transport();
+        let log_tx = self.log_tx.clone();
+        self.spawn_untimed(
+            WalletOp::WatchLogs,
+            log_tx.clone(),
+            move |transports| async move {
+                let web3 = transports.web3(&read)?;
+                let topics = (!topics.is_empty()).then_some(topics);
+                let mut next = from_block;
+                loop {
+                    let latest = web3.eth().block_number().await?.as_u64();
+                    if latest >= next {
+                        let filter = FilterBuilder::default()
+                            .address(vec![address])
+                            .topics(topics.clone(), None, None, None)
+                            .from_block(BlockNumber::Number(next.into()))
+                            .to_block(BlockNumber::Number(latest.into()))
+                            .build();
+                        for log in web3.eth().logs(filter).await? {
+                            if log_tx.send(log).await.is_err() {
+                                return Ok(None);
+                            }
+                        }
+                        next = latest + 1;
+                    }
+                    Delay::new(interval).await;
+                }
+            },
+        );
+    }
+
+    /// The first connected account, the one the wallet has selected.
+    pub fn primary_account(&self) -> Option<H160> {
+        self.accounts.first().copied()
+    }
+
+    /// `primary_account` as a `PeerId` hex string, for zypher networking.
+    pub fn primary_account_peer_hex(&self) -> Option<String> {
+        self.primary_account()
+            .map(|addr| PeerId(addr.to_fixed_bytes()).to_hex())
+    }
+
+    pub fn accounts(&self) -> &[H160] {
+        &self.accounts
+    }
+
+    /// The connected chain, 0 before `connect` resolves. Kept up to date when
+    /// the user switches networks, see `ChainChanged`.
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Whether the wallet is connected to `chain_id`, e.g. to block gameplay
+    /// on the wrong network.
+    pub fn is_on_chain(&self, chain_id: u64) -> bool {
+        self.chain_id == chain_id
+    }
+
+    /// Drain every channel at once, e.g. to handle the results in one place
+    /// without the Bevy events. The results of each channel keep their order.
+    pub fn poll(&mut self) -> Vec<WalletEvent> {
+        let mut events = vec![];
+        loop {
+            match self.recv_account() {
+                Ok((_, chain_id)) => {
+                    events.push(WalletEvent::AccountConnected(AccountConnected {
+                        address: self.accounts[0],
+                        chain_id,
+                    }));
+                }
+                Err(RecvError::NoAccounts) => continue,
+                Err(_) => break,
+            }
+        }
+        while let Ok((id, signature)) = self.recv_signature_with_id() {
+            events.push(WalletEvent::MessageSigned(MessageSigned { id, signature }));
+        }
+        while let Ok((id, hash)) = self.recv_transaction_with_id() {
+            events.push(WalletEvent::TransactionSent(TransactionSent { id, hash }));
+        }
+        while let Ok((id, method, data)) = self.recv_call_with_id() {
+            events.push(WalletEvent::CallReturned(CallReturned { id, method, data }));
+        }
+        while let Ok(chain_id) = self.recv_switch_chain() {
+            events.push(WalletEvent::ChainSwitched(ChainSwitched(chain_id)));
+        }
+        while let Ok(chain_id) = self.recv_add_chain() {
+            events.push(WalletEvent::ChainAdded(ChainAdded(chain_id)));
+        }
+        while let Ok(balance) = self.recv_balance() {
+            events.push(WalletEvent::BalanceFetched(BalanceFetched(balance)));
+        }
+        while let Ok(receipt) = self.recv_receipt() {
+            events.push(WalletEvent::TransactionMined(TransactionMined(receipt)));
+        }
+        while let Ok((id, signature)) = self.recv_personal_signature_with_id() {
+            events.push(WalletEvent::PersonalMessageSigned(PersonalMessageSigned {
+                id,
+                signature,
+            }));
+        }
+        while let Ok((id, signature)) = self.recv_typed_signature_with_id() {
+            events.push(WalletEvent::TypedDataSigned(TypedDataSigned {
+                id,
+                signature,
+            }));
+        }
+        while let Ok(gas) = self.recv_gas_estimate() {
+            events.push(WalletEvent::GasEstimated(GasEstimated(gas)));
+        }
+        while let Ok(log) = self.recv_log() {
+            events.push(WalletEvent::LogReceived(LogReceived(log)));
+        }
+        while let Ok(accounts) = self.recv_accounts_changed() {
+            events.push(WalletEvent::AccountsChanged(AccountsChanged(accounts)));
+        }
+        while let Ok(chain) = self.recv_chain_changed() {
+            events.push(WalletEvent::ChainChanged(ChainChanged(chain)));
+        }
+        while self.recv_disconnected().is_ok() {
+            events.push(WalletEvent::WalletDisconnected(WalletDisconnected));
+        }
+        while let Ok(added) = self.recv_asset_watched() {
+            events.push(WalletEvent::AssetWatched(AssetWatched(added)));
+        }
+        while let Ok(results) = self.recv_multicall() {
+            events.push(WalletEvent::MulticallReturned(MulticallReturned(results)));
+        }
+        while let Ok(permissions) = self.recv_permissions() {
+            events.push(WalletEvent::PermissionsChanged(PermissionsChanged(
+                permissions,
+            )));
+        }
+        while let Ok(nonce) = self.recv_nonce() {
+            events.push(WalletEvent::NonceFetched(NonceFetched(nonce)));
+        }
+        while let Ok(number) = self.recv_block_number() {
+            events.push(WalletEvent::BlockNumberFetched(BlockNumberFetched(number)));
+        }
+        while let Ok(block) = self.recv_new_block() {
+            events.push(WalletEvent::NewBlock(block));
+        }
+        while let Ok(tx) = self.recv_transaction_details() {
+            events.push(WalletEvent::TransactionFetched(TransactionFetched(tx)));
+        }
+        while let Ok(receipt) = self.recv_full_receipt() {
+            events.push(WalletEvent::ReceiptFetched(ReceiptFetched(receipt)));
+        }
+        while let Ok(price) = self.recv_gas_price() {
+            events.push(WalletEvent::GasPriceFetched(GasPriceFetched(price)));
+        }
+        while let Ok(history) = self.recv_fee_history() {
+            events.push(WalletEvent::FeeHistoryFetched(FeeHistoryFetched(history)));
+        }
+        while let Ok(block) = self.recv_block() {
+            events.push(WalletEvent::BlockFetched(Box::new(BlockFetched(block))));
+        }
+        while let Ok(receipt) = self.recv_confirmed() {
+            events.push(WalletEvent::TransactionConfirmed(TransactionConfirmed(
+                receipt,
+            )));
+        }
+        while let Ok((address, code)) = self.recv_code() {
+            events.push(WalletEvent::CodeFetched(CodeFetched { address, code }));
+        }
+        while let Ok(replaced) = self.recv_replaced() {
+            events.push(WalletEvent::TransactionReplaced(replaced));
+        }
+        while let Ok((address, slot, value)) = self.recv_storage() {
+            events.push(WalletEvent::StorageFetched(StorageFetched {
+                address,
+                slot,
+                value,
+            }));
+        }
+        while let Ok((name, address)) = self.recv_ens_address() {
+            events.push(WalletEvent::EnsResolved(EnsResolved { name, address }));
+        }
+        while let Ok((address, name)) = self.recv_ens_name() {
+            events.push(WalletEvent::EnsReverseResolved(EnsReverseResolved {
+                address,
+                name,
+            }));
+        }
+        while let Ok((logs, done)) = self.recv_logs() {
+            events.push(WalletEvent::LogsFetched(LogsFetched { logs, done }));
+        }
+        while let Ok((id, method, tokens)) = self.recv_contract_call_with_id() {
+            events.push(WalletEvent::ContractCallReturned(ContractCallReturned {
+                id,
+                method,
+                tokens,
+            }));
+        }
+        while let Ok((id, hash, receipt)) = self.recv_send_confirmed() {
+            events.push(WalletEvent::ConfirmedTransaction(ConfirmedTransaction {
+                id,
+                hash,
+                receipt,
+            }));
+        }
+        while let Ok(fees) = self.recv_fees() {
+            events.push(WalletEvent::FeesSuggested(FeesSuggested(fees)));
+        }
+        while let Ok(metadata) = self.recv_token_metadata() {
+            events.push(WalletEvent::TokenMetadataFetched(TokenMetadataFetched(
+                metadata,
+            )));
+        }
+        while let Ok(permit) = self.recv_permit() {
+            events.push(WalletEvent::PermitSigned(PermitSigned(permit)));
+        }
+        while let Ok(error) = self.recv_error() {
+            events.push(WalletEvent::OperationFailed(OperationFailed(error)));
+        }
+        events
+    }
+
+    pub fn recv_account(&mut self) -> Result<(String, u64), RecvError> {
+        let (addrs, chain) = self.account_rx.try_recv()?;
+        self.accounts = addrs;
+        self.chain_id = chain;
+        self.connections += 1;
+
+        let addr = self
+            .primary_account_peer_hex()
+            .ok_or(RecvError::NoAccounts)?;
+        Ok((addr, chain))
+    }
+
+    pub fn recv_signature(&self) -> Result<H520, RecvError> {
+        Ok(self.recv_signature_with_id()?.1)
+    }
+
+    /// The next signature with the id `sign` returned for it.
+    pub fn recv_signature_with_id(&self) -> Result<(RequestId, H520), RecvError> {
+        Ok(self.signature_rx.try_recv()?)
+    }
+
+    pub fn recv_transaction(&self) -> Result<H256, RecvError> {
+        Ok(self.recv_transaction_with_id()?.1)
+    }
+
+    /// The next transaction hash with the id `send` returned for it.
+    pub fn recv_transaction_with_id(&self) -> Result<(RequestId, H256), RecvError> {
+        Ok(self.transaction_rx.try_recv()?)
+    }
+
+    pub fn recv_call(&self) -> Result<(String, Vec<u8>), RecvError> {
+        let (_, method, data) = self.recv_call_with_id()?;
+        Ok((method, data))
+    }
+
+    /// The next call result with the id `call` returned for it.
+    pub fn recv_call_with_id(&self) -> Result<(RequestId, String, Vec<u8>), RecvError> {
+        if let Some(result) = self.parked_calls.lock().unwrap().pop_front() {
+            return Ok(result);
+        }
+        Ok(self.call_rx.try_recv()?)
+    }
+
+    /// The result of the call `call` returned `id` for, the other results
+    /// stay for the next `recv_call`.
+    pub fn recv_call_by_id(&self, id: RequestId) -> Result<(String, Vec<u8>), RecvError> {
+        let mut parked = self.parked_calls.lock().unwrap();
+        while let Ok(result) = self.call_rx.try_recv() {
+            parked.push_back(result);
+        }
+        let at = parked
+            .iter()
+            .position(|(call, _, _)| *call == id)
+            .ok_or(RecvError::Empty)?;
+        let (_, method, data) = parked.remove(at).unwrap();
+        Ok((method, data))
+    }
+
+    pub fn recv_switch_chain(&mut self) -> Result<u64, RecvError> {
+        let chain = self.switch_chain_rx.try_recv()?;
+        self.chain_id = chain;
+        Ok(chain)
+    }
+
+    pub fn recv_add_chain(&self) -> Result<u64, RecvError> {
+        Ok(self.add_chain_rx.try_recv()?)
+    }
+
+    pub fn recv_balance(&self) -> Result<U256, RecvError> {
+        Ok(self.balance_rx.try_recv()?)
+    }
+
+    pub fn recv_receipt(&self) -> Result<TxReceipt, RecvError> {
+        Ok(self.receipt_rx.try_recv()?)
+    }
+
+    pub fn recv_personal_signature(&self) -> Result<H520, RecvError> {
+        Ok(self.recv_personal_signature_with_id()?.1)
+    }
+
+    /// The next signature with the id `personal_sign` returned for it.
+    pub fn recv_personal_signature_with_id(&self) -> Result<(RequestId, H520), RecvError> {
+        Ok(self.personal_signature_rx.try_recv()?)
+    }
+
+    pub fn recv_typed_signature(&self) -> Result<H520, RecvError> {
+        Ok(self.recv_typed_signature_with_id()?.1)
+    }
+
+    /// The next signature with the id `sign_typed_data` returned for it.
+    pub fn recv_typed_signature_with_id(&self) -> Result<(RequestId, H520), RecvError> {
+        Ok(self.typed_signature_rx.try_recv()?)
+    }
+
+    pub fn recv_gas_estimate(&self) -> Result<U256, RecvError> {
+        Ok(self.gas_estimate_rx.try_recv()?)
+    }
+
+    pub fn recv_log(&self) -> Result<Log, RecvError> {
+        Ok(self.log_rx.try_recv()?)
+    }
+
+    /// The new accounts from the wallet's `accountsChanged`, empty when it was
+    /// locked. Listening starts with `connect`.
+    pub fn recv_accounts_changed(&mut self) -> Result<Vec<H160>, RecvError> {
+        let accounts = self.accounts_changed_rx.try_recv()?;
+        self.accounts = accounts.clone();
+        Ok(accounts)
+    }
+
+    pub fn recv_chain_changed(&mut self) -> Result<u64, RecvError> {
+        let chain = self.chain_changed_rx.try_recv()?;
+        self.chain_id = chain;
+        Ok(chain)
+    }
+
+    pub fn recv_disconnected(&self) -> Result<(), RecvError> {
+        Ok(self.disconnected_rx.try_recv()?)
+    }
+
+    pub fn recv_asset_watched(&self) -> Result<bool, RecvError> {
+        Ok(self.asset_watched_rx.try_recv()?)
+    }
+
+    pub fn recv_multicall(&self) -> Result<Vec<(bool, Vec<u8>)>, RecvError> {
+        Ok(self.multicall_rx.try_recv()?)
+    }
+
+    pub fn recv_permissions(&self) -> Result<Vec<String>, RecvError> {
+        Ok(self.permissions_rx.try_recv()?)
+    }
+
+    pub fn recv_nonce(&self) -> Result<U256, RecvError> {
+        Ok(self.nonce_rx.try_recv()?)
+    }
+
+    pub fn recv_block_number(&self) -> Result<U256, RecvError> {
+        Ok(self.block_number_rx.try_recv()?)
+    }
+
+    pub fn recv_new_block(&self) -> Result<NewBlock, RecvError> {
+        Ok(self.new_block_rx.try_recv()?)
+    }
+
+    pub fn recv_transaction_details(&self) -> Result<Option<Transaction>, RecvError> {
+        Ok(self.tx_details_rx.try_recv()?)
+    }
+
+    pub fn recv_full_receipt(&self) -> Result<Option<TransactionReceipt>, RecvError> {
+        Ok(self.full_receipt_rx.try_recv()?)
+    }
+
+    pub fn recv_gas_price(&self) -> Result<U256, RecvError> {
+        Ok(self.gas_price_rx.try_recv()?)
+    }
+
+    pub fn recv_fee_history(&self) -> Result<FeeHistory, RecvError> {
+        Ok(self.fee_history_rx.try_recv()?)
+    }
+
+    pub fn recv_block(&self) -> Result<Option<Block<H256>>, RecvError> {
+        Ok(self.block_rx.try_recv()?)
+    }
+
+    /// The receipt of a `watch_transaction` with `WatchOptions::confirmations`.
+    pub fn recv_confirmed(&self) -> Result<TxReceipt, RecvError> {
+        Ok(self.confirmed_rx.try_recv()?)
+    }
+
+    pub fn recv_code(&self) -> Result<(H160, Vec<u8>), RecvError> {
+        Ok(self.code_rx.try_recv()?)
+    }
+
+    pub fn recv_replaced(&self) -> Result<TransactionReplaced, RecvError> {
+        Ok(self.replaced_rx.try_recv()?)
+    }
+
+    pub fn recv_storage(&self) -> Result<(H160, U256, H256), RecvError> {
+        Ok(self.storage_rx.try_recv()?)
+    }
+
+    pub fn recv_ens_address(&self) -> Result<(String, Option<H160>), RecvError> {
+        Ok(self.ens_address_rx.try_recv()?)
+    }
+
+    pub fn recv_ens_name(&self) -> Result<(H160, Option<String>), RecvError> {
+        Ok(self.ens_name_rx.try_recv()?)
+    }
+
+    pub fn recv_logs(&self) -> Result<(Vec<Log>, bool), RecvError> {
+        Ok(self.logs_rx.try_recv()?)
+    }
+
+    pub fn recv_contract_call(&self) -> Result<(String, Vec<Token>), RecvError> {
+        let (_, method, tokens) = self.recv_contract_call_with_id()?;
+        Ok((method, tokens))
+    }
+
+    /// The next `call_contract` result with the id it returned.
+    pub fn recv_contract_call_with_id(&self) -> Result<(RequestId, String, Vec<Token>), RecvError> {
+        Ok(self.contract_call_rx.try_recv()?)
+    }
+
+    pub fn recv_send_confirmed(&self) -> Result<(RequestId, H256, TxReceipt), RecvError> {
+        Ok(self.send_confirmed_rx.try_recv()?)
+    }
+
+    pub fn recv_fees(&self) -> Result<FeeSuggestion, RecvError> {
+        Ok(self.fees_rx.try_recv()?)
+    }
+
+    pub fn recv_token_metadata(&self) -> Result<TokenMetadata, RecvError> {
+        Ok(self.token_metadata_rx.try_recv()?)
+    }
+
+    pub fn recv_permit(&self) -> Result<PermitSignature, RecvError> {
+        Ok(self.permit_rx.try_recv()?)
+    }
+
+    pub fn recv_error(&self) -> Result<Web3OpError, RecvError> {
+        Ok(self.error_rx.try_recv()?)
+    }
+}
+
+fn suggest_fees(history: FeeHistory) -> Result<FeeSuggestion, Web3Error> {
+    // the history includes the base fee of the block after the newest one,
+    // it is empty on chains without EIP-1559
+    let base_fee = *history
+        .base_fee_per_gas
+        .last()
+        .ok_or(Web3Error::UnsupportedMethod)?;
+    let mut rewards: Vec<U256> = history
+        .reward
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|r| r.first().copied())
+        .collect();
+    rewards.sort();
+    let max_priority_fee = rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+    Ok(FeeSuggestion {
+        base_fee,
+        max_priority_fee,
+        max_fee: base_fee * 2 + max_priority_fee,
+    })
+}
+
+/// Delay before the first retry of a read, doubled after each attempt.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// `task`, or `Web3Error::Timeout` if it takes longer than `timeout`.
+async fn timed<T>(
+    timeout: Duration,
+    task: impl Future<Output = Result<T, Web3Error>>,
+) -> Result<T, Web3Error> {
+    match select(pin!(task), Delay::new(timeout)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Web3Error::Timeout),
+    }
+}
+
+/// A transaction from `EthWallet::submit`, sent once its task is started.
+struct Submit {
+    transport: TransportConfig,
+    sent: Arc<Mutex<Vec<Transaction>>>,
+    from: H160,
+    to: Option<H160>,
+    data: Vec<u8>,
+    options: SendOptions,
+}
+
+impl Submit {
+    async fn send(self, transports: Transports) -> Result<H256, Web3Error> {
+        let Submit {
+            transport,
+            sent,
+            from,
+            to,
+            data,
+            options,
+        } = self;
+        send_recorded(&transports, transport, sent, from, to, data, options).await
+    }
+}
+
+/// Send the transaction through `transport`, with its local key if set, and
+/// record it for `EthWallet::replace_transaction`. The nonce is picked here
+/// rather than read back, a transaction just sent is often still unknown to
+/// `eth_getTransactionByHash`.
+async fn send_recorded(
+    transports: &Transports,
+    transport: TransportConfig,
+    sent: Arc<Mutex<Vec<Transaction>>>,
+    from: H160,
+    to: Option<H160>,
+    data: Vec<u8>,
+    options: SendOptions,
+) -> Result<H256, Web3Error> {
+    let web3 = transports.web3(&transport)?;
+    #[cfg(feature = "http")]
+    let key = transport.local_key();
+    #[cfg(feature = "http")]
+    let from = key.map_or(from, |key| SecretKeyRef::new(&key).address());
+
+    let nonce = match options.nonce {
+        Some(nonce) => nonce,
+        None => {
+            let pending = Some(BlockNumber::Pending);
+            web3.eth().transaction_count(from, pending).await?
+        }
+    };
+    let options = SendOptions {
+        nonce: Some(nonce),
+        ..options
+    };
+    let record = Transaction {
+        nonce,
+        from: Some(from),
+        to,
+        value: options.value.unwrap_or_default(),
+        gas: options.gas.unwrap_or_default(),
+        gas_price: options.gas_price,
+        max_fee_per_gas: options.max_fee_per_gas,
+        max_priority_fee_per_gas: options.max_priority_fee_per_gas,
+        input: data.clone().into(),
+        ..Default::default()
+    };
+
+    #[cfg(feature = "http")]
+    let hash = match key {
+        Some(key) => transport::send_local(&web3, key, to, data, options).await?,
+        None => {
+            let txr = options.request(from, to, data);
+            web3.eth().send_transaction(txr).await?
+        }
+    };
+    #[cfg(not(feature = "http"))]
+    let hash = {
+        let txr = options.request(from, to, data);
+        web3.eth().send_transaction(txr).await?
+    };
+    sent.lock().unwrap().push(Transaction { hash, ..record });
+    Ok(hash)
+}
+
+/// The destination, data and options replacing `original`: its nonce, the
+/// fees unset in `options` raised by 10% over the original ones (the minimum
+/// nodes accept for a replacement), and an empty self-send if `cancel`.
+fn replacement(
+    original: &Transaction,
+    from: H160,
+    options: SendOptions,
+    cancel: bool,
+) -> (Option<H160>, Vec<u8>, SendOptions) {
+    let bump = |fee: U256| fee + fee / 10 + 1;
+    let mut options = SendOptions {
+        nonce: Some(original.nonce),
+        ..options
+    };
+    if options.gas_price.is_none() && !options.is_eip1559() {
+        if let Some(max_fee) = original.max_fee_per_gas {
+            options.max_fee_per_gas = Some(bump(max_fee));
+            options.max_priority_fee_per_gas =
+                Some(bump(original.max_priority_fee_per_gas.unwrap_or_default()));
+        } else {
+            options.gas_price = original.gas_price.map(bump);
+        }
+    }
+    if cancel {
+        return (Some(from), vec![], options);
+    }
+    options.value = options.value.or(Some(original.value));
+    // zero when the wallet estimated it, leave it to the wallet again
+    options.gas = options
+        .gas
+        .or((!original.gas.is_zero()).then_some(original.gas));
+    (original.to, original.input.0.clone(), options)
+}
+
+/// Poll for the receipt of `hash` as described by `EthWallet::watch_transaction`.
+async fn poll_receipt(
+    web3: &Web3<WalletTransport>,
+    hash: H256,
+    options: WatchOptions,
+) -> Result<TransactionReceipt, Web3Error> {
+    let mut mined_in = None;
+    for _ in 0..options.max_attempts {
+        let receipt = web3.eth().transaction_receipt(hash).await?;
+        let block = receipt
+            .as_ref()
+            .and_then(|r| r.block_hash.zip(r.block_number));
+        match (block, mined_in) {
+            (Some(block), None) => mined_in = Some(block),
+            (block, Some(mined)) if block != Some(mined) => {
+                return Err(Web3Error::Reorged(hash));
+            }
+            _ => {}
+        }
+        if let (Some(receipt), Some((_, number))) = (receipt, mined_in) {
+            if options.confirmations == 0 {
+                return Ok(receipt);
+            }
+            let head = web3.eth().block_number().await?;
+            if head >= number + options.confirmations {
+                return Ok(receipt);
+            }
+        }
+        Delay::new(options.interval).await;
+    }
+    Err(Web3Error::Timeout)
+}
+
+fn drain<T>(rx: &Receiver<T>) {
+    while rx.try_recv().is_ok() {}
+}
+
+/// Format a wei amount as ether, e.g. `1500000000000000000` as `1.5`.
+pub fn format_ether(wei: U256) -> String {
+    let (whole, frac) = wei.div_mod(U256::exp10(18));
+    if frac.is_zero() {
+        return whole.to_string();
+    }
+
+    let frac = format!("{:018}", frac.as_u64());
+    format!("{}.{}", whole, frac.trim_end_matches('0'))
+}
+
+#[derive(Debug)]
+pub enum ContractError {
+    BadAddress(FromHexError),
+    BadAbi(EthError),
+    UnknownMethod {
+        method: String,
+        available: Vec<String>,
+    },
+    Encode {
+        method: String,
+        expected: Vec<ParamType>,
+        source: EthError,
+    },
+    Decode {
+        method: String,
+        len: usize,
+        source: EthError,
+    },
+    UnknownEvent {
+        event: String,
+        available: Vec<String>,
+    },
+    DecodeLog {
+        event: String,
+        source: EthError,
+    },
+    /// A human-readable ABI entry that could not be parsed.
+    BadSignature(String),
+    /// The calldata does not start with the selector of `method`.
+    SelectorMismatch {
+        method: String,
+        expected: [u8; 4],
+        found: Vec<u8>,
+    },
+}
+
+impl fmt::Display for ContractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContractError::BadAddress(e) => write!(f, "invalid contract address: {}", e),
+            ContractError::BadAbi(e) => write!(f, "invalid contract abi: {}", e),
+            ContractError::UnknownMethod { method, available } => write!(
+                f,
+                "unknown contract method `{}`, available: [{}]",
+                method,
+                available.join(", ")
+            ),
+            ContractError::Encode {
+                method,
+                expected,
+                source,
+            } => {
+                let expected: Vec<String> = expected.iter().map(|p| p.to_string()).collect();
+                write!(
+                    f,
+                    "failed to encode `{}({})`: {}",
+                    method,
+                    expected.join(","),
+                    source
+                )
+            }
+            ContractError::Decode {
+                method,
+                len,
+                source,
+            } => write!(
+                f,
+                "failed to decode `{}` output from {} bytes: {}",
+                method, len, source
+            ),
+            ContractError::UnknownEvent { event, available } => write!(
+                f,
+                "unknown contract event `{}`, available: [{}]",
+                event,
+                available.join(", ")
+            ),
+            ContractError::DecodeLog { event, source } => {
+                write!(f, "failed to decode `{}` log: {}", event, source)
+            }
+            ContractError::BadSignature(signature) => {
+                write!(f, "invalid abi signature `{}`", signature)
+            }
+            ContractError::SelectorMismatch {
+                method,
+                expected,
+                found,
+            } => write!(
+                f,
+                "calldata selector 0x{} is not `{}` (0x{})",
+                found.to_hex::<String>(),
+                method,
+                expected.to_hex::<String>()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ContractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ContractError::BadAddress(e) => Some(e),
+            ContractError::BadAbi(e) => Some(e),
+            ContractError::UnknownMethod { .. } => None,
+            ContractError::Encode { source, .. } => Some(source),
+            ContractError::Decode { source, .. } => Some(source),
+            ContractError::UnknownEvent { .. } => None,
+            ContractError::DecodeLog { source, .. } => Some(source),
+            ContractError::BadSignature(_) => None,
+            ContractError::SelectorMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<FromHexError> for ContractError {
+    fn from(e: FromHexError) -> ContractError {
+        ContractError::BadAddress(e)
+    }
+}
+
+impl From<EthError> for ContractError {
+    fn from(e: EthError) -> ContractError {
+        ContractError::BadAbi(e)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Contract {
+    pub address: H160,
+    abi: EthContract,
+}
+
+impl Contract {
+    pub fn is_empty(&self) -> bool {
+        self.address == H160::default()
+    }
+
+    pub fn address(&self) -> String {
+        let addr = PeerId(self.address.to_fixed_bytes());
+        addr.to_hex()
+    }
+
+    pub fn load(address: &str, json: &[u8]) -> Result<Self, ContractError> {
+        let address = address.parse()?;
+        let abi = EthContract::load(json)?;
+        Ok(Contract { address, abi })
+    }
+
+    /// `load` from human-readable signatures instead of a JSON ABI, e.g.
+    /// `"function transfer(address to, uint256 amount) returns (bool)"` or
+    /// `"event Transfer(address indexed from, address indexed to, uint256 value)"`.
+    pub fn load_human(address: &str, signatures: &[&str]) -> Result<Self, ContractError> {
+        Self::load(address, &human_abi::to_json(signatures)?)
+    }
+
+    /// Same as `load`, but panics on a malformed address or abi.
+    pub fn load_unchecked(address: &str, json: &[u8]) -> Self {
+        Self::load(address, json).unwrap()
+    }
+
+    pub fn has_method(&self, name: &str) -> bool {
+        self.abi.functions.contains_key(name)
+    }
+
+    /// The 4-byte selector calldata of `method` starts with.
+    pub fn selector(&self, method: &str) -> Result<[u8; 4], ContractError> {
+        Ok(self.function(method)?.short_signature())
+    }
+
+    /// The canonical signature the selector is hashed from, e.g.
+    /// `transfer(address,uint256)`.
+    pub fn signature(&self, method: &str) -> Result<String, ContractError> {
+        Ok(canonical(self.function(method)?))
+    }
+
+    /// The canonical signature of every function of the ABI, overloads
+    /// included, e.g. to check a configured method list at load.
+    pub fn signatures(&self) -> Vec<String> {
+        self.abi.functions().map(canonical).collect()
+    }
+
+    /// The function names of the ABI, overloads listed once.
+    pub fn functions(&self) -> Vec<String> {
+        self.abi.functions.keys().cloned().collect()
+    }
+
+    /// The event names of the ABI, overloads listed once.
+    pub fn events(&self) -> Vec<String> {
+        self.abi.events.keys().cloned().collect()
+    }
+
+    /// Same as `functions`, sorted by name.
+    pub fn function_names(&self) -> Vec<String> {
+        self.functions()
+    }
+
+    /// Same as `events`, sorted by name.
+    pub fn event_names(&self) -> Vec<String> {
+        self.events()
+    }
+
+    /// The canonical signature of the function `name`, or `None` if the ABI
+    /// has none. For an overloaded name it is the first overload of the ABI,
+    /// see `signatures` for all of them.
+    pub fn function_signature(&self, name: &str) -> Option<String> {
+        self.signature(name).ok()
+    }
+
+    fn function(&self, method: &str) -> Result<&Function, ContractError> {
+        self.abi
+            .function(method)
+            .map_err(|_| ContractError::UnknownMethod {
+                method: method.to_owned(),
+                available: self.abi.functions.keys().cloned().collect(),
+            })
+    }
+
+    pub fn encode(&self, method: &str, tokens: &[Token]) -> Result<Vec<u8>, ContractError> {
+        let function = self.function(method)?;
+        function
+            .encode_input(tokens)
+            .map_err(|source| ContractError::Encode {
+                method: method.to_owned(),
+                expected: function.inputs.iter().map(|p| p.kind.clone()).collect(),
+                source,
+            })
+    }
+
+    pub fn decode(&self, method: &str, bytes: &[u8]) -> Result<Vec<Token>, ContractError> {
+        self.function(method)?
+            .decode_output(bytes)
+            .map_err(|source| ContractError::Decode {
+                method: method.to_owned(),
+                len: bytes.len(),
+                source,
+            })
+    }
+
+    /// Decode the arguments of `method` from transaction calldata, the
+    /// inverse of `encode`.
+    pub fn decode_input(&self, method: &str, bytes: &[u8]) -> Result<Vec<Token>, ContractError> {
+        let function = self.function(method)?;
+        let expected = function.short_signature();
+        if bytes.len() < 4 || bytes[..4] != expected {
+            return Err(ContractError::SelectorMismatch {
+                method: method.to_owned(),
+                expected,
+                found: bytes[..bytes.len().min(4)].to_vec(),
+            });
+        }
+        function
+            .decode_input(&bytes[4..])
+            .map_err(|source| ContractError::Decode {
+                method: method.to_owned(),
+                len: bytes.len(),
+                source,
+            })
+    }
+
+    fn event(&self, event: &str) -> Result<&EthEvent, ContractError> {
+        self.abi
+            .event(event)
+            .map_err(|_| ContractError::UnknownEvent {
+                event: event.to_owned(),
+                available: self.abi.events.keys().cloned().collect(),
+            })
+    }
+
+    /// Load one of the abis embedded in the crate.
+    pub(crate) fn embedded(address: H160, json: &[u8]) -> Self {
+        Contract {
+            address,
+            abi: EthContract::load(json).expect("embedded abi is valid"),
+        }
+    }
+
+    /// `encode`, for params known to match the abi.
+    pub(crate) fn encode_known(&self, method: &str, tokens: &[Token]) -> Vec<u8> {
+        self.encode(method, tokens)
+            .expect("params match the embedded abi")
+    }
+
+    /// `wallet.call` of `method`, delivered under the method name.
+    pub(crate) fn call_known(
+        &self,
+        wallet: &EthWallet,
+        method: &str,
+        tokens: &[Token],
+    ) -> RequestId {
+        wallet.call(
+            self.address,
+            method.to_owned(),
+            self.encode_known(method, tokens),
+        )
+    }
+
+    /// `decode` into a Rust type, e.g. `U256` for `balanceOf`. Several return
+    /// values decode into a tuple, e.g. `(U256, H160)`.
+    pub fn decode_one<T: FromToken>(&self, method: &str, bytes: &[u8]) -> Result<T, ContractError> {
+        let mut tokens = self.decode(method, bytes)?;
+        let token = match tokens.len() {
+            1 => tokens.pop(),
+            _ => Some(Token::Tuple(tokens)),
+        };
+        token
+            .and_then(T::from_token)
+            .ok_or_else(|| ContractError::Decode {
+                method: method.to_owned(),
+                len: bytes.len(),
+                source: EthError::InvalidData,
+            })
+    }
+
+    /// Deployment data: `bytecode` followed by the encoded constructor arguments.
+    pub fn encode_deploy(
+        &self,
+        bytecode: &[u8],
+        tokens: &[Token],
+    ) -> Result<Vec<u8>, ContractError> {
+        match &self.abi.constructor {
+            Some(constructor) => {
+                constructor
+                    .encode_input(bytecode.to_vec(), tokens)
+                    .map_err(|source| ContractError::Encode {
+                        method: "constructor".to_owned(),
+                        expected: constructor.inputs.iter().map(|p| p.kind.clone()).collect(),
+                        source,
+                    })
+            }
+            None if tokens.is_empty() => Ok(bytecode.to_vec()),
+            None => Err(ContractError::Encode {
+                method: "constructor".to_owned(),
+                expected: vec![],
+                source: EthError::InvalidData,
+            }),
+        }
+    }
+
+    /// The topic 0 hash of `event`, to filter logs by.
+    pub fn event_signature(&self, event: &str) -> Result<H256, ContractError> {
+        Ok(self.event(event)?.signature())
+    }
+
+    /// Decode the indexed topics and data of `log`, in the order of the event params.
+    pub fn decode_event(&self, event: &str, log: &Log) -> Result<Vec<Token>, ContractError> {
+        let raw = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.0.clone(),
+        };
+        let parsed =
+            self.event(event)?
+                .parse_log(raw)
+                .map_err(|source| ContractError::DecodeLog {
+                    event: event.to_owned(),
+                    source,
+                })?;
+        Ok(parsed.params.into_iter().map(|p| p.value).collect())
+    }
+
+    /// Decode a custom error of the ABI from a revert payload, e.g. the `data`
+    /// of `Web3Error::Reverted`. `None` if no error has its selector.
+    pub fn decode_error(&self, data: &[u8]) -> Option<(String, Vec<Token>)> {
+        if data.len() < 4 {
+            return None;
+        }
+        let (selector, args) = data.split_at(4);
+        self.abi
+            .errors()
+            .find(|error| error.signature()[..4] == *selector)
+            .and_then(|error| Some((error.name.clone(), error.decode(args).ok()?)))
+    }
+
+    /// A readable revert reason: the standard `Error(string)` and
+    /// `Panic(uint256)` ones, or a custom error of the ABI written like
+    /// `InsufficientBalance(100, 250)`.
+    pub fn decode_revert(&self, data: &[u8]) -> Option<String> {
+        decode_revert_reason(data).or_else(|| {
+            let (name, tokens) = self.decode_error(data)?;
+            let args: Vec<_> = tokens.iter().map(format_token).collect();
+            Some(format!("{}({})", name, args.join(", ")))
+        })
+    }
+
+    /// `decode_event` with the event picked by the topic 0 of `log`, e.g. for
+    /// the mixed logs of `EthWallet::get_logs`. `None` if the ABI has no
+    /// matching event (or it is anonymous).
+    pub fn decode_any_event(&self, log: &Log) -> Option<(String, Vec<Token>)> {
+        let topic = log.topics.first()?;
+        let event = self
+            .abi
+            .events()
+            .find(|e| !e.anonymous && e.signature() == *topic)?;
+        let tokens = self.decode_event(&event.name, log).ok()?;
+        Some((event.name.clone(), tokens))
     }
 }
@@ -0,0 +1,153 @@
+use futures_timer::Delay;
+use jsonrpc_core::{Call, ErrorCode, Params, Value};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use web3::{error::TransportError, futures::future::LocalBoxFuture, helpers, RequestId, Transport};
+
+/// A transport answering with canned responses and recording the requests it
+/// received, to drive `EthWallet` in tests through `TransportConfig::Mock`.
+///
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_web3::{EthWallet, MockTransport, TransportConfig, WalletPlugin};
+/// use serde_json::json;
+///
+/// let mock = MockTransport::new();
+/// mock.respond("eth_requestAccounts", json!(["0x0000000000000000000000000000000000000001"]));
+/// mock.respond("eth_chainId", json!("0x1"));
+///
+/// let mut app = App::new();
+/// app.add_plugins(TaskPoolPlugin::default())
+///     .add_plugins(WalletPlugin::builder().transport(TransportConfig::Mock(mock.clone())));
+/// app.update();
+/// app.world.resource::<EthWallet>().connect();
+/// app.update();
+///
+/// let mut wallet = app.world.resource_mut::<EthWallet>();
+/// assert_eq!(wallet.recv_account().unwrap().1, 1);
+/// assert_eq!(mock.requests()[0].0, "eth_requestAccounts");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+    next_id: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    responses: HashMap<String, Result<Value, jsonrpc_core::Error>>,
+    sequences: HashMap<String, VecDeque<Value>>,
+    requests: Vec<(String, Vec<Value>)>,
+    hanging: HashSet<String>,
+    delays: HashMap<String, Duration>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer every `method` request with `result`.
+    pub fn respond(&self, method: &str, result: Value) {
+        let mut state = self.state.lock().unwrap();
+        state.responses.insert(method.to_owned(), Ok(result));
+    }
+
+    /// Answer the `method` requests with `results` in turn, the last one for
+    /// all the following, e.g. a block number going up.
+    pub fn respond_sequence(&self, method: &str, results: Vec<Value>) {
+        let mut state = self.state.lock().unwrap();
+        state.sequences.insert(method.to_owned(), results.into());
+    }
+
+    /// Answer every `method` request with an RPC error, e.g. `USER_REJECTED`.
+    pub fn respond_error(&self, method: &str, code: i64, message: &str) {
+        let error = jsonrpc_core::Error {
+            code: ErrorCode::from(code),
+            message: message.to_owned(),
+            data: None,
+        };
+        let mut state = self.state.lock().unwrap();
+        state.responses.insert(method.to_owned(), Err(error));
+    }
+
+    /// Never answer `method` requests, like a wallet popup left open, to
+    /// check the `OperationPolicy` timeout.
+    pub fn hang(&self, method: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.hanging.insert(method.to_owned());
+    }
+
+    /// Answer `method` requests only after `delay`, like a slow node or a
+    /// user taking time to approve.
+    pub fn delay(&self, method: &str, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.delays.insert(method.to_owned(), delay);
+    }
+
+    /// The method and params of each request received so far, in order.
+    pub fn requests(&self) -> Vec<(String, Vec<Value>)> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl PartialEq for MockTransport {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl Transport for MockTransport {
+    type Out = LocalBoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+        let Call::MethodCall(call) = request else {
+            let e = TransportError::Message("unsupported request".to_owned());
+            return Box::pin(async move { Err(web3::Error::Transport(e)) });
+        };
+        let params = match call.params {
+            Params::Array(params) => params,
+            Params::Map(map) => vec![Value::Object(map)],
+            Params::None => vec![],
+        };
+        let mut state = self.state.lock().unwrap();
+        state.requests.push((call.method.clone(), params));
+        if state.hanging.contains(&call.method) {
+            return Box::pin(future::pending());
+        }
+        let result = if let Some(sequence) = state.sequences.get_mut(&call.method) {
+            Ok(match sequence.len() {
+                0 | 1 => sequence.front().cloned().unwrap_or_default(),
+                _ => sequence.pop_front().unwrap(),
+            })
+        } else {
+            match state.responses.get(&call.method) {
+                Some(Ok(value)) => Ok(value.clone()),
+                Some(Err(e)) => Err(web3::Error::Rpc(e.clone())),
+                None => Err(web3::Error::Transport(TransportError::Message(format!(
+                    "no mock response for {}",
+                    call.method
+                )))),
+            }
+        };
+        match state.delays.get(&call.method).copied() {
+            Some(delay) => Box::pin(async move {
+                Delay::new(delay).await;
+                result
+            }),
+            None => Box::pin(async move { result }),
+        }
+    }
+}
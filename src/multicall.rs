@@ -0,0 +1,62 @@
+use web3::{
+    ethabi::{self, ParamType, Token},
+    types::H160,
+};
+
+use crate::ContractError;
+
+/// Multicall3, deployed at the same address on most chains.
+pub const MULTICALL3: H160 = H160([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+
+const TRY_AGGREGATE: &str = "tryAggregate";
+
+fn call_type() -> ParamType {
+    ParamType::Array(Box::new(ParamType::Tuple(vec![
+        ParamType::Address,
+        ParamType::Bytes,
+    ])))
+}
+
+fn result_type() -> ParamType {
+    ParamType::Array(Box::new(ParamType::Tuple(vec![
+        ParamType::Bool,
+        ParamType::Bytes,
+    ])))
+}
+
+/// `tryAggregate(false, calls)`, a failing call doesn't revert the others.
+pub(crate) fn encode(calls: Vec<(H160, Vec<u8>)>) -> Vec<u8> {
+    let calls = calls
+        .into_iter()
+        .map(|(target, data)| Token::Tuple(vec![Token::Address(target), Token::Bytes(data)]))
+        .collect();
+    let mut data = ethabi::short_signature(TRY_AGGREGATE, &[ParamType::Bool, call_type()]).to_vec();
+    data.extend(ethabi::encode(&[Token::Bool(false), Token::Array(calls)]));
+    data
+}
+
+/// The `(success, return data)` of each call.
+pub(crate) fn decode(data: &[u8]) -> Result<Vec<(bool, Vec<u8>)>, ContractError> {
+    let decode_error = |source| ContractError::Decode {
+        method: TRY_AGGREGATE.to_owned(),
+        len: data.len(),
+        source,
+    };
+    let mut tokens = ethabi::decode(&[result_type()], data).map_err(decode_error)?;
+    let Some(Token::Array(results)) = tokens.pop() else {
+        return Err(decode_error(ethabi::Error::InvalidData));
+    };
+    results
+        .into_iter()
+        .map(|result| match result {
+            Token::Tuple(fields) => match fields.as_slice() {
+                [Token::Bool(success), Token::Bytes(data)] => Ok((*success, data.clone())),
+                _ => Err(decode_error(ethabi::Error::InvalidData)),
+            },
+            _ => Err(decode_error(ethabi::Error::InvalidData)),
+        })
+        .collect()
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+use web3::{
+    signing::{hash_message, keccak256, recover, RecoveryError},
+    types::{H160, H520},
+};
+
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The recovery byte is neither 0/1 nor 27/28.
+    InvalidV(u8),
+    Recovery(RecoveryError),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::InvalidV(v) => write!(f, "invalid signature recovery byte {}", v),
+            SignatureError::Recovery(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// The address that signed the keccak256 hash of `message`.
+pub fn recover_signer(message: &[u8], signature: H520) -> Result<H160, SignatureError> {
+    recover_hash(&keccak256(message), signature)
+}
+
+/// The address that signed `message` with `personal_sign`, i.e. with the
+/// `\x19Ethereum Signed Message:\n<len>` prefix.
+pub fn recover_personal_signer(message: &[u8], signature: H520) -> Result<H160, SignatureError> {
+    recover_hash(hash_message(message).as_bytes(), signature)
+}
+
+fn recover_hash(hash: &[u8], signature: H520) -> Result<H160, SignatureError> {
+    let v = signature[64];
+    let recovery_id = match v {
+        0 | 1 => v,
+        27 | 28 => v - 27,
+        _ => return Err(SignatureError::InvalidV(v)),
+    };
+    recover(hash, &signature[..64], recovery_id as i32).map_err(SignatureError::Recovery)
+}
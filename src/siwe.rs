@@ -0,0 +1,392 @@
+use rustc_hex::ToHex;
+use std::{fmt, str::FromStr};
+use web3::{signing::keccak256, types::H160};
+
+#[cfg(feature = "signing")]
+use crate::{recover_personal_signer, SignatureError};
+#[cfg(feature = "signing")]
+use web3::types::H520;
+
+use crate::{EthWallet, RequestId};
+
+const HEADER: &str = " wants you to sign in with your Ethereum account:";
+
+/// A Sign-In With Ethereum (EIP-4361) message, rendered to the canonical
+/// string with `to_string` and read back with `parse`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: H160,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    /// RFC 3339 timestamps.
+    pub issued_at: String,
+    pub expiration_time: Option<String>,
+    pub not_before: Option<String>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+impl SiweMessage {
+    pub fn new(
+        domain: &str,
+        address: H160,
+        uri: &str,
+        chain_id: u64,
+        nonce: &str,
+        issued_at: &str,
+    ) -> Self {
+        SiweMessage {
+            domain: domain.to_owned(),
+            address,
+            statement: None,
+            uri: uri.to_owned(),
+            version: "1".to_owned(),
+            chain_id,
+            nonce: nonce.to_owned(),
+            issued_at: issued_at.to_owned(),
+            expiration_time: None,
+            not_before: None,
+            request_id: None,
+            resources: vec![],
+        }
+    }
+
+    pub fn with_statement(mut self, statement: &str) -> Self {
+        self.statement = Some(statement.to_owned());
+        self
+    }
+
+    pub fn with_expiration_time(mut self, expiration_time: &str) -> Self {
+        self.expiration_time = Some(expiration_time.to_owned());
+        self
+    }
+
+    pub fn with_not_before(mut self, not_before: &str) -> Self {
+        self.not_before = Some(not_before.to_owned());
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.request_id = Some(request_id.to_owned());
+        self
+    }
+
+    pub fn with_resource(mut self, resource: &str) -> Self {
+        self.resources.push(resource.to_owned());
+        self
+    }
+
+    pub fn parse(message: &str) -> Result<SiweMessage, SiweError> {
+        let mut lines = message.split('\n').peekable();
+
+        let domain = lines
+            .next()
+            .and_then(|l| l.strip_suffix(HEADER))
+            .ok_or(SiweError::Malformed("header"))?;
+        let text = lines.next().ok_or(SiweError::Malformed("address"))?;
+        if !text.starts_with("0x") {
+            return Err(SiweError::Malformed("address"));
+        }
+        let address: H160 = text[2..]
+            .parse()
+            .map_err(|_| SiweError::Malformed("address"))?;
+        // the signed text must render back the same, see `Display`
+        if text != checksum(&address) {
+            return Err(SiweError::InvalidChecksum(text.to_owned()));
+        }
+        expect_empty(lines.next())?;
+
+        let statement = match lines.next() {
+            Some("") => None,
+            Some(statement) if !statement.starts_with("URI: ") => {
+                expect_empty(lines.next())?;
+                Some(statement.to_owned())
+            }
+            _ => return Err(SiweError::Malformed("statement")),
+        };
+
+        let mut field = |name: &'static str| {
+            lines
+                .next()
+                .and_then(|l| l.strip_prefix(name))
+                .and_then(|l| l.strip_prefix(": "))
+                .map(str::to_owned)
+                .ok_or(SiweError::Malformed(name))
+        };
+        let uri = field("URI")?;
+        let version = field("Version")?;
+        if version != "1" {
+            return Err(SiweError::UnsupportedVersion(version));
+        }
+        let chain_id = field("Chain ID")?
+            .parse()
+            .map_err(|_| SiweError::Malformed("Chain ID"))?;
+        let nonce = field("Nonce")?;
+        let issued_at = field("Issued At")?;
+
+        let mut message = SiweMessage {
+            domain: domain.to_owned(),
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time: None,
+            not_before: None,
+            request_id: None,
+            resources: vec![],
+        };
+
+        let mut optional = |name: &str| {
+            let value = lines
+                .peek()
+                .and_then(|l| l.strip_prefix(name))
+                .and_then(|l| l.strip_prefix(": "))
+                .map(str::to_owned);
+            if value.is_some() {
+                lines.next();
+            }
+            value
+        };
+        message.expiration_time = optional("Expiration Time");
+        message.not_before = optional("Not Before");
+        message.request_id = optional("Request ID");
+
+        if lines.next_if_eq(&"Resources:").is_some() {
+            for line in lines.by_ref() {
+                let resource = line
+                    .strip_prefix("- ")
+                    .ok_or(SiweError::Malformed("Resources"))?;
+                message.resources.push(resource.to_owned());
+            }
+        }
+        if lines.next().is_some() {
+            return Err(SiweError::Malformed("trailing lines"));
+        }
+        Ok(message)
+    }
+
+    /// Check that `signature` is the `personal_sign` of this message by
+    /// `address`, and that the message matches `opts`.
+    #[cfg(feature = "signing")]
+    pub fn verify(&self, signature: H520, opts: &VerifyOpts) -> Result<(), SiweError> {
+        if self.version != "1" {
+            return Err(SiweError::UnsupportedVersion(self.version.clone()));
+        }
+        if let Some(domain) = opts.domain.as_ref().filter(|d| **d != self.domain) {
+            return Err(SiweError::DomainMismatch(domain.clone()));
+        }
+        if let Some(nonce) = opts.nonce.as_ref().filter(|n| **n != self.nonce) {
+            return Err(SiweError::NonceMismatch(nonce.clone()));
+        }
+        if let Some(now) = opts.now {
+            let now = now as i64;
+            if let Some(expiration_time) = &self.expiration_time {
+                let time =
+                    unix_time(expiration_time).ok_or(SiweError::Malformed("Expiration Time"))?;
+                if now >= time {
+                    return Err(SiweError::Expired);
+                }
+            }
+            if let Some(not_before) = &self.not_before {
+                let time = unix_time(not_before).ok_or(SiweError::Malformed("Not Before"))?;
+                if now < time {
+                    return Err(SiweError::NotYetValid);
+                }
+            }
+        }
+        let signer = recover_personal_signer(self.to_string().as_bytes(), signature)
+            .map_err(SiweError::Signature)?;
+        if signer != self.address {
+            return Err(SiweError::SignerMismatch(signer));
+        }
+        Ok(())
+    }
+}
+
+/// What `SiweMessage::verify` checks besides the signature, each check
+/// skipped when unset.
+#[cfg(feature = "signing")]
+#[derive(Clone, Debug, Default)]
+pub struct VerifyOpts {
+    /// The domain the message must be for, i.e. the host of the site.
+    pub domain: Option<String>,
+    /// The nonce issued to the client for this sign-in.
+    pub nonce: Option<String>,
+    /// The current time in seconds since the unix epoch (e.g.
+    /// `js_sys::Date::now() / 1000.0` in the browser), which must fall
+    /// between `not_before` and `expiration_time`.
+    pub now: Option<u64>,
+}
+
+/// Seconds since the unix epoch of an RFC 3339 timestamp, e.g.
+/// `2021-09-30T16:25:24Z` or `2021-09-30T18:25:24.5+02:00`.
+#[cfg(feature = "signing")]
+fn unix_time(timestamp: &str) -> Option<i64> {
+    let (date, time) = timestamp.split_once(['T', 't'])?;
+    let mut date = date.splitn(3, '-').map(|n| n.parse::<u32>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, offset) = time.split_at(time.find(['Z', 'z', '+', '-'])?);
+    let mut time = time
+        .splitn(3, ':')
+        .map(|n| n.split('.').next()?.parse::<u32>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let offset = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let seconds = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            if offset.starts_with('-') {
+                -seconds
+            } else {
+                seconds
+            }
+        }
+    };
+
+    // days from the civil date, counting years from March so the leap day is last
+    let (year, month) = (year as i64, month as i64);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days * 86_400 + (hour * 3600 + minute * 60 + second) as i64 - offset)
+}
+
+fn expect_empty(line: Option<&str>) -> Result<(), SiweError> {
+    match line {
+        Some("") => Ok(()),
+        _ => Err(SiweError::Malformed("empty line")),
+    }
+}
+
+/// `address` with the EIP-55 mixed-case checksum.
+fn checksum(address: &H160) -> String {
+    let hex: String = address.as_bytes().to_hex();
+    let hash = keccak256(hex.as_bytes());
+    let mut out = String::from("0x");
+    for (i, c) in hex.chars().enumerate() {
+        let byte = hash[i / 2];
+        let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+        if nibble >= 8 {
+            out.push(c.to_ascii_uppercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+impl fmt::Display for SiweMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}{}", self.domain, HEADER)?;
+        writeln!(f, "{}", checksum(&self.address))?;
+        writeln!(f)?;
+        if let Some(statement) = &self.statement {
+            writeln!(f, "{}", statement)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "URI: {}", self.uri)?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Chain ID: {}", self.chain_id)?;
+        writeln!(f, "Nonce: {}", self.nonce)?;
+        write!(f, "Issued At: {}", self.issued_at)?;
+        if let Some(expiration_time) = &self.expiration_time {
+            write!(f, "\nExpiration Time: {}", expiration_time)?;
+        }
+        if let Some(not_before) = &self.not_before {
+            write!(f, "\nNot Before: {}", not_before)?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, "\nRequest ID: {}", request_id)?;
+        }
+        if !self.resources.is_empty() {
+            write!(f, "\nResources:")?;
+            for resource in &self.resources {
+                write!(f, "\n- {}", resource)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SiweMessage {
+    type Err = SiweError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SiweMessage::parse(s)
+    }
+}
+
+#[derive(Debug)]
+pub enum SiweError {
+    /// The named line is missing or not in the EIP-4361 format.
+    Malformed(&'static str),
+    UnsupportedVersion(String),
+    /// The address is not in the EIP-55 mixed case the message must use.
+    InvalidChecksum(String),
+    #[cfg(feature = "signing")]
+    Signature(SignatureError),
+    /// The message was signed by this address instead of its own.
+    #[cfg(feature = "signing")]
+    SignerMismatch(H160),
+    /// The message is for another domain than this expected one.
+    #[cfg(feature = "signing")]
+    DomainMismatch(String),
+    /// The message has another nonce than this expected one.
+    #[cfg(feature = "signing")]
+    NonceMismatch(String),
+    #[cfg(feature = "signing")]
+    Expired,
+    #[cfg(feature = "signing")]
+    NotYetValid,
+}
+
+impl fmt::Display for SiweError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SiweError::Malformed(line) => write!(f, "malformed SIWE message: {}", line),
+            SiweError::UnsupportedVersion(v) => write!(f, "unsupported SIWE version {}", v),
+            SiweError::InvalidChecksum(address) => {
+                write!(f, "SIWE address {} is not EIP-55 checksummed", address)
+            }
+            #[cfg(feature = "signing")]
+            SiweError::Signature(e) => write!(f, "{}", e),
+            #[cfg(feature = "signing")]
+            SiweError::SignerMismatch(signer) => {
+                write!(f, "SIWE message signed by {:?}", signer)
+            }
+            #[cfg(feature = "signing")]
+            SiweError::DomainMismatch(domain) => write!(f, "SIWE message is not for {}", domain),
+            #[cfg(feature = "signing")]
+            SiweError::NonceMismatch(nonce) => {
+                write!(f, "SIWE message nonce is not {}", nonce)
+            }
+            #[cfg(feature = "signing")]
+            SiweError::Expired => write!(f, "SIWE message expired"),
+            #[cfg(feature = "signing")]
+            SiweError::NotYetValid => write!(f, "SIWE message is not valid yet"),
+        }
+    }
+}
+
+impl std::error::Error for SiweError {}
+
+impl EthWallet {
+    /// `personal_sign` the canonical string of `message`, delivered through
+    /// `recv_personal_signature` and `PersonalMessageSigned`.
+    pub fn sign_siwe(&self, account: H160, message: SiweMessage) -> RequestId {
+        self.personal_sign(account, message.to_string())
+    }
+}
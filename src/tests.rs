@@ -0,0 +1,2247 @@
+use bevy::prelude::*;
+use serde_json::{json, Value};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::*;
+#[cfg(feature = "ws")]
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+const ACCOUNT: &str = "0x00000000000000000000000000000000000000aa";
+
+fn config(mock: &MockTransport) -> WalletConfig {
+    WalletPlugin::builder().transport(TransportConfig::Mock(mock.clone()))
+}
+
+/// An app with `config`, started.
+fn app_with(config: WalletConfig) -> App {
+    let mut app = App::new();
+    app.add_plugins(TaskPoolPlugin::default())
+        .add_plugins(config);
+    app.update();
+    app
+}
+
+fn app(mock: &MockTransport) -> App {
+    app_with(config(mock))
+}
+
+fn wallet(app: &mut App) -> Mut<'_, EthWallet> {
+    app.world.resource_mut::<EthWallet>()
+}
+
+/// Update `app` until `f` returns something, for the requests that don't
+/// resolve on their first poll.
+fn until<T>(app: &mut App, mut f: impl FnMut(&mut App) -> Option<T>) -> T {
+    let start = Instant::now();
+    loop {
+        app.update();
+        if let Some(value) = f(app) {
+            return value;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn account() -> H160 {
+    ACCOUNT.parse().unwrap()
+}
+
+/// A mock answering the requests of `connect` with `ACCOUNT` on `chain_id`.
+fn connected_mock(chain_id: u64) -> MockTransport {
+    let mock = MockTransport::new();
+    mock.respond("eth_requestAccounts", json!([ACCOUNT]));
+    mock.respond("eth_accounts", json!([ACCOUNT]));
+    mock.respond("eth_chainId", json!(format!("{:#x}", chain_id)));
+    mock
+}
+
+#[test]
+fn connect_reads_accounts_and_chain() {
+    let mock = connected_mock(5);
+    let mut app = app(&mock);
+    wallet(&mut app).connect();
+    app.update();
+
+    let (_, chain_id) = wallet(&mut app).recv_account().unwrap();
+    assert_eq!(chain_id, 5);
+    assert_eq!(wallet(&mut app).accounts, vec![account()]);
+    let methods: Vec<_> = mock.requests().into_iter().map(|(m, _)| m).collect();
+    assert_eq!(methods, ["eth_requestAccounts", "eth_chainId"]);
+}
+
+/// A node of its own knowing only the account and chain id.
+#[derive(Debug)]
+struct ChainOnly(u64);
+
+impl Web3Transport for ChainOnly {
+    fn execute(
+        &self,
+        method: &str,
+        _params: Vec<Value>,
+    ) -> web3::futures::future::LocalBoxFuture<'static, web3::Result<Value>> {
+        let result = match method {
+            "eth_requestAccounts" => Ok(json!([ACCOUNT])),
+            "eth_chainId" => Ok(json!(format!("{:#x}", self.0))),
+            _ => Err(web3::Error::Unreachable),
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[test]
+fn custom_transports_plug_into_the_wallet() {
+    let custom = TransportConfig::custom(ChainOnly(10));
+    let mut app = app_with(WalletPlugin::builder().transport(custom));
+    wallet(&mut app).connect();
+    let (_, chain_id) = until(&mut app, |app| wallet(app).recv_account().ok());
+    assert_eq!(chain_id, 10);
+    assert_eq!(wallet(&mut app).accounts, vec![account()]);
+
+    // any `web3::Transport`, boxed
+    let mock = connected_mock(7);
+    let boxed: std::sync::Arc<dyn Web3Transport> = std::sync::Arc::new(mock.clone());
+    let custom = TransportConfig::Custom(boxed.into());
+    assert_eq!(custom, custom.clone());
+    let mut app = app_with(WalletPlugin::builder().transport(custom));
+    wallet(&mut app).connect();
+    let (_, chain_id) = until(&mut app, |app| wallet(app).recv_account().ok());
+    assert_eq!(chain_id, 7);
+    assert_eq!(mock.requests()[0].0, "eth_requestAccounts");
+}
+
+/// The events sent since the last `update` but one.
+fn events<E: Event + Clone>(app: &App) -> Vec<E> {
+    let events = app.world.resource::<Events<E>>();
+    let mut reader = events.get_reader();
+    reader.read(events).cloned().collect()
+}
+
+const SIGNATURE: &str = concat!(
+    "0x1111111111111111111111111111111111111111111111111111111111111111",
+    "2222222222222222222222222222222222222222222222222222222222222222",
+    "1b",
+);
+
+#[test]
+fn results_stay_for_recv_by_default() {
+    let mock = connected_mock(1);
+    mock.respond("eth_sign", json!(SIGNATURE));
+    let mut app = app(&mock);
+
+    let id = wallet(&mut app).sign_addr(account(), b"hello".to_vec());
+    app.update();
+    app.update();
+    assert!(events::<MessageSigned>(&app).is_empty());
+    let (signed, _) = wallet(&mut app).recv_signature_with_id().unwrap();
+    assert_eq!(signed, id);
+}
+
+#[test]
+fn results_become_events_when_enabled() {
+    let mock = connected_mock(1);
+    mock.respond("eth_sign", json!(SIGNATURE));
+    let mut app = app_with(config(&mock).emit_events(true));
+
+    let id = wallet(&mut app).sign_addr(account(), b"hello".to_vec());
+    app.update();
+    app.update();
+    let signed = events::<MessageSigned>(&app);
+    assert_eq!(signed.len(), 1);
+    assert_eq!(signed[0].id, id);
+    assert_eq!(wallet(&mut app).recv_signature(), Err(RecvError::Empty));
+}
+
+/// The params of the `method` requests `mock` received.
+fn params(mock: &MockTransport, method: &str) -> Vec<Vec<Value>> {
+    mock.requests()
+        .into_iter()
+        .filter(|(m, _)| m == method)
+        .map(|(_, params)| params)
+        .collect()
+}
+
+#[test]
+fn default_chain_switches_after_recv_account() {
+    let mock = connected_mock(1);
+    mock.respond("wallet_switchEthereumChain", Value::Null);
+    let mut app = app_with(config(&mock).default_chain(5));
+
+    wallet(&mut app).connect();
+    app.update();
+    assert!(params(&mock, "wallet_switchEthereumChain").is_empty());
+    wallet(&mut app).recv_account().unwrap();
+    app.update();
+    app.update();
+    assert_eq!(
+        params(&mock, "wallet_switchEthereumChain"),
+        [vec![json!({ "chainId": "0x5" })]]
+    );
+}
+
+#[test]
+fn default_chain_switches_with_events() {
+    let mock = connected_mock(5);
+    mock.respond("wallet_switchEthereumChain", Value::Null);
+    let mut app = app_with(config(&mock).default_chain(5).emit_events(true));
+
+    // already on the default chain
+    wallet(&mut app).connect();
+    app.update();
+    assert!(params(&mock, "wallet_switchEthereumChain").is_empty());
+
+    mock.respond("eth_chainId", json!("0x1"));
+    wallet(&mut app).connect();
+    until(&mut app, |_| {
+        (params(&mock, "wallet_switchEthereumChain").len() == 1).then_some(())
+    });
+}
+
+fn hash(byte: u8) -> H256 {
+    H256::repeat_byte(byte)
+}
+
+/// A successful receipt of `hash` mined in block `block`.
+fn receipt(hash: H256, block: u64) -> Value {
+    json!(TransactionReceipt {
+        transaction_hash: hash,
+        block_hash: Some(H256::from_low_u64_be(block)),
+        block_number: Some(block.into()),
+        status: Some(1.into()),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn replacement_reuses_nonce_with_higher_fee() {
+    let mock = connected_mock(1);
+    mock.respond("eth_getTransactionCount", json!("0x7"));
+    mock.respond("eth_sendTransaction", json!(hash(1)));
+    mock.respond("eth_getTransactionReceipt", receipt(hash(1), 10));
+    let mut app = app(&mock);
+    let to = H160::repeat_byte(2);
+
+    let options = SendOptions {
+        gas_price: Some(100.into()),
+        ..Default::default()
+    };
+    wallet(&mut app).send_with(account(), to, vec![1, 2], options);
+    let sent = until(&mut app, |app| wallet(app).recv_transaction().ok());
+    assert_eq!(sent, hash(1));
+    // the nonce is known without reading the transaction back
+    assert!(params(&mock, "eth_getTransactionByHash").is_empty());
+    assert_eq!(wallet(&mut app).sent_transactions()[0].nonce, 7.into());
+
+    wallet(&mut app).replace_transaction(7.into(), SendOptions::default());
+    let replaced = until(&mut app, |app| wallet(app).recv_replaced().ok());
+    assert_eq!((replaced.nonce, replaced.replaced), (7.into(), true));
+
+    let sends = params(&mock, "eth_sendTransaction");
+    assert_eq!(sends[0][0]["nonce"], json!("0x7"));
+    assert_eq!(sends[1][0]["nonce"], json!("0x7"));
+    assert_eq!(sends[1][0]["gasPrice"], json!("0x6f"));
+    assert_eq!(sends[1][0]["to"], json!(to));
+    assert_eq!(sends[1][0]["data"], json!("0x0102"));
+}
+
+#[test]
+fn cancel_bumps_the_fees_the_wallet_picked() {
+    let mock = connected_mock(1);
+    mock.respond("eth_getTransactionCount", json!("0x3"));
+    mock.respond("eth_sendTransaction", json!(hash(1)));
+    mock.respond("eth_getTransactionReceipt", receipt(hash(1), 10));
+    mock.respond(
+        "eth_getTransactionByHash",
+        json!(Transaction {
+            hash: hash(1),
+            nonce: 3.into(),
+            from: Some(account()),
+            max_fee_per_gas: Some(100.into()),
+            max_priority_fee_per_gas: Some(10.into()),
+            ..Default::default()
+        }),
+    );
+    let mut app = app(&mock);
+
+    wallet(&mut app).send_with(
+        account(),
+        H160::repeat_byte(2),
+        vec![1],
+        SendOptions::default(),
+    );
+    until(&mut app, |app| wallet(app).recv_transaction().ok());
+    wallet(&mut app).cancel_transaction(3.into(), SendOptions::default());
+    until(&mut app, |app| wallet(app).recv_replaced().ok());
+
+    let cancel = &params(&mock, "eth_sendTransaction")[1][0];
+    assert_eq!(cancel["nonce"], json!("0x3"));
+    assert_eq!(cancel["to"], json!(account()));
+    assert_eq!(cancel["data"], json!("0x"));
+    assert_eq!(cancel["maxFeePerGas"], json!("0x6f"));
+    assert_eq!(cancel["maxPriorityFeePerGas"], json!("0xc"));
+}
+
+#[test]
+fn replacing_unknown_nonce_fails() {
+    let mock = connected_mock(1);
+    let mut app = app(&mock);
+
+    let id = wallet(&mut app).replace_transaction(9.into(), SendOptions::default());
+    let error = wallet(&mut app).recv_error().unwrap();
+    assert_eq!((error.op, error.id), (WalletOp::Replace, Some(id)));
+    assert!(matches!(error.error, Web3Error::InvalidParams(_)));
+}
+
+#[test]
+fn concurrent_calls_map_to_their_ids() {
+    let mock = connected_mock(1);
+    mock.respond_sequence(
+        "eth_call",
+        vec![json!("0x01"), json!("0x02"), json!("0x03")],
+    );
+    let mut app = app(&mock);
+    let to = H160::repeat_byte(2);
+
+    let first = wallet(&mut app).call(to, "first".to_owned(), vec![]);
+    let second = wallet(&mut app).call(to, "second".to_owned(), vec![]);
+    let third = wallet(&mut app).call(to, "third".to_owned(), vec![]);
+    assert!(first != second && second != third);
+    app.update();
+
+    let wallet = wallet(&mut app);
+    assert_eq!(
+        wallet.recv_call_by_id(second),
+        Ok(("second".to_owned(), vec![2]))
+    );
+    assert_eq!(wallet.recv_call_by_id(second), Err(RecvError::Empty));
+    // the results read past are still delivered in order
+    assert_eq!(
+        wallet.recv_call_with_id(),
+        Ok((first, "first".to_owned(), vec![1]))
+    );
+    assert_eq!(wallet.recv_call(), Ok(("third".to_owned(), vec![3])));
+}
+
+#[cfg(feature = "walletconnect")]
+#[test]
+fn wallet_connect_keeps_wallet_settings() {
+    let mock = connected_mock(1);
+    let mut app = App::new();
+    app.add_plugins(TaskPoolPlugin::default())
+        .add_plugins(config(&mock).default_chain(5).emit_events(true))
+        .add_plugins(WalletConnectPlugin {
+            project_id: "project".to_owned(),
+            chains: vec![5],
+        });
+    app.update();
+
+    let config = app.world.resource::<WalletConfig>();
+    assert_eq!(config.transport, TransportConfig::WalletConnect);
+    assert_eq!((config.default_chain, config.emit_events), (Some(5), true));
+    assert!(app.world.contains_resource::<WalletConnect>());
+}
+
+fn short_timeout(retries: u32) -> OperationPolicy {
+    OperationPolicy {
+        timeout: Duration::from_millis(50),
+        retries,
+    }
+}
+
+#[test]
+fn unanswered_request_times_out() {
+    let mock = connected_mock(1);
+    mock.hang("eth_sign");
+    let mut app = app_with(config(&mock).operation_policy(short_timeout(2)));
+
+    let id = wallet(&mut app).sign_addr(account(), b"hello".to_vec());
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!((error.op, error.id), (WalletOp::Sign, Some(id)));
+    assert!(matches!(error.error, Web3Error::Timeout));
+    // signing is never retried
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[test]
+fn unanswered_read_is_retried() {
+    let mock = connected_mock(1);
+    mock.hang("eth_blockNumber");
+    let mut app = app_with(config(&mock).operation_policy(short_timeout(1)));
+
+    wallet(&mut app).block_number();
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert!(matches!(error.error, Web3Error::Timeout));
+    assert_eq!(mock.requests().len(), 2);
+}
+
+/// A JSON-RPC node on localhost answering each method with its `responses`
+/// entry, returning its url.
+#[cfg(feature = "http")]
+fn rpc_server(responses: Vec<(&'static str, Value)>) -> String {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = BufReader::new(stream.unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).unwrap();
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            let result = responses
+                .iter()
+                .find(|(method, _)| request["method"] == *method)
+                .map_or(Value::Null, |(_, result)| result.clone());
+            let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+            let response = response.to_string();
+            write!(
+                stream.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    });
+    url
+}
+
+/// `WalletPlugin::http` against `url`, inside a tokio runtime for reqwest.
+#[cfg(feature = "http")]
+fn http_app(url: &str) -> (App, tokio::runtime::Runtime) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut app = App::new();
+    app.add_plugins(TaskPoolPlugin::default())
+        .add_plugins(WalletPlugin::http(url));
+    app.update();
+    (app, runtime)
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_reads_balance_and_call() {
+    let url = rpc_server(vec![
+        ("eth_getBalance", json!("0x2a")),
+        ("eth_call", json!("0x01")),
+    ]);
+    let (mut app, runtime) = http_app(&url);
+    let _guard = runtime.enter();
+
+    wallet(&mut app).balance(account(), None);
+    let balance = until(&mut app, |app| wallet(app).recv_balance().ok());
+    assert_eq!(balance, 42.into());
+
+    let id = wallet(&mut app).call(account(), "ping".to_owned(), vec![]);
+    let (call_id, method, data) = until(&mut app, |app| wallet(app).recv_call_with_id().ok());
+    assert_eq!((call_id, method.as_str(), data), (id, "ping", vec![1]));
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_without_node_reports_error() {
+    let (mut app, runtime) = http_app("http://127.0.0.1:1");
+    let _guard = runtime.enter();
+
+    wallet(&mut app).block_number();
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::BlockNumber);
+    assert!(error.error.is_transient());
+}
+
+/// A step of a scripted websocket session.
+#[cfg(feature = "ws")]
+enum WsStep {
+    /// Wait for a request of this method and answer it.
+    Answer(&'static str, Value),
+    /// Wait for a request of this method and fail it.
+    Reject(&'static str),
+    /// Push a log to the subscription.
+    Push(Value),
+}
+
+/// A websocket node on localhost, returning its url, the requests it
+/// received and the number of connections. Each connection sending a request
+/// plays the next of `sessions` then closes, the others are left open.
+#[cfg(feature = "ws")]
+fn ws_server(sessions: Vec<Vec<WsStep>>) -> (String, Arc<Mutex<Vec<Value>>>, Arc<AtomicU64>) {
+    use soketto::handshake::{server::Response, Server};
+    use web3::futures::{executor::block_on, io::AllowStdIo};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let sessions = Arc::new(Mutex::new(VecDeque::from(sessions)));
+    let requests = Arc::new(Mutex::new(vec![]));
+    let received = requests.clone();
+    let connections = Arc::new(AtomicU64::new(0));
+    let connected = connections.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            connections.fetch_add(1, Ordering::SeqCst);
+            let sessions = sessions.clone();
+            let requests = requests.clone();
+            thread::spawn(move || {
+                block_on(async {
+                    let mut server = Server::new(AllowStdIo::new(stream.unwrap()));
+                    let key = server.receive_request().await.unwrap().key();
+                    let accept = Response::Accept {
+                        key,
+                        protocol: None,
+                    };
+                    server.send_response(&accept).await.unwrap();
+                    let (mut sender, mut receiver) = server.into_builder().finish();
+
+                    // connections without requests, e.g. the one of `WsWalletPlugin`,
+                    // block here and take no session
+                    let mut message = vec![];
+                    receiver.receive_data(&mut message).await.unwrap();
+                    let Some(session) = sessions.lock().unwrap().pop_front() else {
+                        // no more sessions, leave the client waiting
+                        while receiver.receive_data(&mut message).await.is_ok() {}
+                        return;
+                    };
+                    let mut pending = Some(serde_json::from_slice::<Value>(&message).unwrap());
+                    for step in session {
+                        let id = match &step {
+                            WsStep::Answer(method, _) | WsStep::Reject(method) => {
+                                let request: Value = match pending.take() {
+                                    Some(request) => request,
+                                    None => {
+                                        message.clear();
+                                        receiver.receive_data(&mut message).await.unwrap();
+                                        serde_json::from_slice(&message).unwrap()
+                                    }
+                                };
+                                assert_eq!(request["method"], *method);
+                                requests.lock().unwrap().push(request.clone());
+                                request["id"].clone()
+                            }
+                            WsStep::Push(_) => Value::Null,
+                        };
+                        let text = match step {
+                            WsStep::Answer(_, result) => {
+                                json!({ "jsonrpc": "2.0", "id": id, "result": result })
+                            }
+                            WsStep::Reject(_) => {
+                                let error = json!({ "code": -32601, "message": "unsupported" });
+                                json!({ "jsonrpc": "2.0", "id": id, "error": error })
+                            }
+                            WsStep::Push(log) => {
+                                // web3 registers the subscription after reading its id
+                                thread::sleep(Duration::from_millis(50));
+                                let params = json!({ "subscription": "0x1", "result": log });
+                                json!({ "jsonrpc": "2.0", "method": "eth_subscription", "params": params })
+                            }
+                        };
+                        sender.send_text(text.to_string()).await.unwrap();
+                        sender.flush().await.unwrap();
+                    }
+                    sender.close().await.unwrap();
+                });
+            });
+        }
+    });
+    (url, received, connected)
+}
+
+/// The return data of a `tryAggregate` whose calls returned `results`, `None`
+/// for a reverted call.
+fn multicall_result(results: Vec<Option<Token>>) -> Value {
+    let results = results
+        .into_iter()
+        .map(|result| {
+            let data = result.map(|token| web3::ethabi::encode(&[token]));
+            Token::Tuple(vec![
+                Token::Bool(data.is_some()),
+                Token::Bytes(data.unwrap_or_default()),
+            ])
+        })
+        .collect();
+    let data = web3::ethabi::encode(&[Token::Array(results)]);
+    json!(Bytes(data))
+}
+
+/// The typed data of the first `eth_signTypedData_v4` request, sent as a string.
+fn signed_typed_data(mock: &MockTransport) -> Value {
+    let params = params(mock, "eth_signTypedData_v4");
+    serde_json::from_str(params[0][1].as_str().unwrap()).unwrap()
+}
+
+#[test]
+fn permit_is_signed_for_the_usdc_domain() {
+    // the wallet isn't connected, the chain id comes from `eth_chainId`
+    let mock = connected_mock(1);
+    let separator: H256 = "06c37168a7db5138defc7866392bb87a741f9b3d104deb5094588ce041cae335"
+        .parse()
+        .unwrap();
+    mock.respond(
+        "eth_call",
+        multicall_result(vec![
+            Some(Token::String("USD Coin".into())),
+            Some(Token::String("2".into())),
+            Some(Token::Uint(7.into())),
+            Some(Token::FixedBytes(separator.as_bytes().to_vec())),
+        ]),
+    );
+    // a recovery id rather than 27/28
+    mock.respond("eth_signTypedData_v4", json!(SIGNATURE.replace("1b", "00")));
+    let mut app = app(&mock);
+    let usdc: H160 = "a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".parse().unwrap();
+    let spender = H160::repeat_byte(0xbb);
+
+    Erc20::new(usdc).sign_permit(
+        &wallet(&mut app),
+        account(),
+        spender,
+        100.into(),
+        1000.into(),
+    );
+    let permit = until(&mut app, |app| wallet(app).recv_permit().ok());
+    assert_eq!(permit.nonce, 7.into());
+    assert_eq!(permit.v, 27);
+    assert_eq!(permit.r, H256::repeat_byte(0x11));
+
+    let typed_data = signed_typed_data(&mock);
+    assert_eq!(
+        typed_data["domain"],
+        json!({
+            "name": "USD Coin",
+            "version": "2",
+            "chainId": 1,
+            "verifyingContract": format!("{usdc:?}"),
+        })
+    );
+    assert_eq!(typed_data["message"]["nonce"], "7");
+    assert_eq!(typed_data["message"]["value"], "100");
+}
+
+#[test]
+fn permit_without_separator_uses_the_wallet_chain() {
+    let mock = connected_mock(137);
+    mock.respond(
+        "eth_call",
+        multicall_result(vec![
+            Some(Token::String("Token".into())),
+            None,
+            Some(Token::Uint(0.into())),
+            None,
+        ]),
+    );
+    mock.respond("eth_signTypedData_v4", json!(SIGNATURE));
+    let mut app = app(&mock);
+
+    Erc20::new(H160::repeat_byte(0xcc)).sign_permit(
+        &wallet(&mut app),
+        account(),
+        H160::repeat_byte(0xbb),
+        1.into(),
+        1.into(),
+    );
+    until(&mut app, |app| wallet(app).recv_permit().ok());
+    let typed_data = signed_typed_data(&mock);
+    assert_eq!(typed_data["domain"]["chainId"], 137);
+}
+
+/// Log `index` of block `block`.
+fn log(block: u64, index: u64) -> Value {
+    json!({
+        "address": ACCOUNT,
+        "topics": [],
+        "data": "0x",
+        "blockHash": hash(block as u8),
+        "blockNumber": format!("{block:#x}"),
+        "logIndex": format!("{index:#x}"),
+    })
+}
+
+#[cfg(feature = "ws")]
+#[test]
+fn log_subscription_catches_up_once_after_reconnecting() {
+    use web3::types::FilterBuilder;
+
+    let (url, requests, connections) = ws_server(vec![
+        vec![
+            WsStep::Answer("eth_subscribe", json!("0x1")),
+            WsStep::Push(log(1, 0)),
+        ],
+        vec![
+            WsStep::Answer("eth_subscribe", json!("0x1")),
+            // pushed by the new subscription and fetched by the catch-up,
+            // which starts at the last block delivered
+            WsStep::Push(log(2, 0)),
+            WsStep::Answer("eth_getLogs", json!([log(1, 0), log(1, 1), log(2, 0)])),
+            WsStep::Push(log(2, 0)),
+            WsStep::Push(log(3, 0)),
+        ],
+    ]);
+    let mut app = App::new();
+    app.add_plugins(TaskPoolPlugin::default())
+        .add_plugins(config(&MockTransport::new()))
+        .add_plugins(WsWalletPlugin::new(&url));
+    app.update();
+    let filter = FilterBuilder::default().build();
+    wallet(&mut app).subscribe_logs(filter);
+
+    // (block, log index) of the logs received so far
+    let mut logs = vec![];
+    let mut receive = |app: &mut App| {
+        while let Ok(log) = wallet(app).recv_log() {
+            let index = log.log_index.unwrap().as_u64();
+            logs.push((log.block_number.unwrap().as_u64(), index));
+        }
+        logs.len()
+    };
+    until(&mut app, |app| (receive(app) >= 4).then_some(()));
+    // leave time for a duplicate to arrive
+    for _ in 0..20 {
+        app.update();
+        thread::sleep(Duration::from_millis(5));
+    }
+    receive(&mut app);
+    assert_eq!(logs, [(1, 0), (1, 1), (2, 0), (3, 0)]);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests[2]["params"][0]["fromBlock"], "0x1");
+    // the subscription used the connection of `WsWalletPlugin`, then one new one
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    assert!(app.world.resource::<WsConnection>().is_connected());
+}
+
+#[cfg(feature = "ws")]
+#[test]
+fn head_subscription_stops_when_rejected() {
+    let (url, requests, _) = ws_server(vec![vec![WsStep::Reject("eth_subscribe")]]);
+    let mut app = App::new();
+    app.add_plugins(TaskPoolPlugin::default())
+        .add_plugins(config(&MockTransport::new()))
+        .add_plugins(WsWalletPlugin::new(&url));
+    app.update();
+
+    wallet(&mut app).subscribe_new_heads();
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::WsSubscribe);
+    assert!(matches!(error.error, Web3Error::Rpc(_)), "{error:?}");
+    // no retry
+    for _ in 0..20 {
+        app.update();
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert!(wallet(&mut app).recv_error().is_err());
+}
+
+/// Set in the process running a test alone, see `run_alone`.
+const ALONE: &str = "BEVY_WEB3_TEST_ALONE";
+
+/// Run the test `name` alone in a new process, for the tests that need the
+/// global `IoTaskPool` missing while the other tests create it.
+fn run_alone(name: &str) -> std::process::Output {
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args([name, "--exact", "--nocapture"])
+        .env(ALONE, "1")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("running 1 test"));
+    output
+}
+
+/// An app without `TaskPoolPlugin`.
+fn app_without_pool(config: WalletConfig) -> App {
+    let mut app = App::new();
+    app.add_plugins(config);
+    app.update();
+    app
+}
+
+#[test]
+fn missing_pool_panics_when_required() {
+    if std::env::var_os(ALONE).is_none() {
+        let output = run_alone("tests::missing_pool_panics_when_required");
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("WalletPlugin requires the IoTaskPool"),
+            "{stderr}"
+        );
+        return;
+    }
+    let config = config(&MockTransport::new()).task_pool_policy(TaskPoolPolicy::RequireExisting);
+    app_without_pool(config);
+}
+
+#[test]
+fn missing_pool_is_created_by_default() {
+    if std::env::var_os(ALONE).is_none() {
+        let output = run_alone("tests::missing_pool_is_created_by_default");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return;
+    }
+    let mock = connected_mock(1);
+    let mut app = app_without_pool(config(&mock).task_pool_policy(TaskPoolPolicy::CreateDefault));
+    assert!(IoTaskPool::try_get().is_some());
+
+    wallet(&mut app).connect();
+    app.update();
+    assert_eq!(wallet(&mut app).recv_account().unwrap().1, 1);
+}
+
+#[test]
+fn missing_pool_fails_requests_when_required() {
+    if std::env::var_os(ALONE).is_none() {
+        let output = run_alone("tests::missing_pool_fails_requests_when_required");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return;
+    }
+    // without `WalletPlugin` there is no startup check to panic in
+    let mut wallet = EthWallet::new(TransportConfig::Mock(connected_mock(1)));
+    let mut tasks = WalletTasks::new(TaskPoolPolicy::RequireExisting);
+    wallet.connect();
+    tasks.run(&wallet);
+
+    let error = wallet.recv_error().unwrap();
+    assert_eq!(error.op, WalletOp::Connect);
+    assert!(
+        matches!(error.error, Web3Error::TaskPoolUnavailable),
+        "{error:?}"
+    );
+    assert!(wallet.recv_account().is_err());
+    assert!(IoTaskPool::try_get().is_none());
+}
+
+const ABI: &[u8] = br#"[{
+    "type": "function",
+    "name": "ping",
+    "inputs": [{ "name": "value", "type": "uint256" }],
+    "outputs": [{ "name": "", "type": "bool" }],
+    "stateMutability": "view"
+}]"#;
+
+#[test]
+fn load_unchecked_loads_a_valid_contract() {
+    let contract = Contract::load_unchecked(ACCOUNT, ABI);
+    assert_eq!(contract.address, account());
+    assert!(contract.has_method("ping"));
+}
+
+#[test]
+#[should_panic]
+fn load_unchecked_panics_on_bad_address() {
+    Contract::load_unchecked("0xnot-an-address", ABI);
+}
+
+#[test]
+fn load_reports_garbage_abi() {
+    let Err(error) = Contract::load(ACCOUNT, b"{ not json") else {
+        panic!("loaded garbage");
+    };
+    assert!(matches!(error, ContractError::BadAbi(_)), "{error:?}");
+}
+
+#[test]
+fn load_reports_non_hex_address() {
+    let Err(error) = Contract::load("0xzz00000000000000000000000000000000000000", ABI) else {
+        panic!("loaded a non-hex address");
+    };
+    assert!(matches!(error, ContractError::BadAddress(_)), "{error:?}");
+}
+
+#[test]
+fn encode_reports_unknown_method() {
+    let contract = Contract::load(ACCOUNT, ABI).unwrap();
+    let error = contract.encode("pong", &[]).unwrap_err();
+    assert!(
+        matches!(&error, ContractError::UnknownMethod { method, available } if method == "pong" && available == &["ping"]),
+        "{error:?}"
+    );
+    let error = contract.decode("pong", &[]).unwrap_err();
+    assert!(
+        matches!(error, ContractError::UnknownMethod { .. }),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn encode_reports_wrong_token_count() {
+    let contract = Contract::load(ACCOUNT, ABI).unwrap();
+    let error = contract.encode("ping", &[]).unwrap_err();
+    assert!(
+        matches!(&error, ContractError::Encode { expected, .. } if expected == &[ParamType::Uint(256)]),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn encode_reports_wrong_token_type() {
+    let contract = Contract::load(ACCOUNT, ABI).unwrap();
+    let error = contract.encode("ping", &[Token::Bool(true)]).unwrap_err();
+    assert!(matches!(error, ContractError::Encode { .. }), "{error:?}");
+}
+
+#[test]
+fn encode_and_decode_round_trip() {
+    let contract = Contract::load(ACCOUNT, ABI).unwrap();
+    let data = contract.encode("ping", &[Token::Uint(7.into())]).unwrap();
+    assert_eq!(data[..4], contract.selector("ping").unwrap());
+    let returned = web3::ethabi::encode(&[Token::Bool(true)]);
+    assert_eq!(
+        contract.decode("ping", &returned).unwrap(),
+        [Token::Bool(true)]
+    );
+    let error = contract.decode("ping", &returned[..8]).unwrap_err();
+    assert!(
+        matches!(error, ContractError::Decode { len: 8, .. }),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn encode_deploy_appends_constructor_args() {
+    let bytecode = [0x60, 0x80, 0x60, 0x40];
+    let token =
+        Contract::load_human(ACCOUNT, &["constructor(string name, uint256 supply)"]).unwrap();
+    let args = [Token::String("Token".to_owned()), Token::Uint(1000.into())];
+    let data = token.encode_deploy(&bytecode, &args).unwrap();
+    assert_eq!(data[..4], bytecode);
+    assert_eq!(data[4..], web3::ethabi::encode(&args));
+
+    let error = token.encode_deploy(&bytecode, &[]).unwrap_err();
+    assert!(matches!(error, ContractError::Encode { .. }), "{error:?}");
+
+    // without a constructor only the bytecode is sent
+    let contract = Contract::load(ACCOUNT, ABI).unwrap();
+    assert_eq!(contract.encode_deploy(&bytecode, &[]).unwrap(), bytecode);
+    assert!(contract.encode_deploy(&bytecode, &args).is_err());
+}
+
+#[test]
+fn deploy_contract_sends_encoded_data() {
+    let mock = connected_mock(1);
+    mock.respond("eth_getTransactionCount", json!("0x0"));
+    mock.respond("eth_sendTransaction", json!(hash(1)));
+    let mut app = app(&mock);
+    let bytecode = [0x60, 0x80];
+    let token = Contract::load_human(ACCOUNT, &["constructor(uint256 supply)"]).unwrap();
+    let args = [Token::Uint(1000.into())];
+
+    wallet(&mut app).deploy_contract(account(), &token, &bytecode, &args);
+    let sent = until(&mut app, |app| wallet(app).recv_transaction().ok());
+    assert_eq!(sent, hash(1));
+    let request = &params(&mock, "eth_sendTransaction")[0][0];
+    let data = token.encode_deploy(&bytecode, &args).unwrap();
+    assert_eq!(request["data"], json!(Bytes(data)));
+    assert!(request.get("to").is_none());
+
+    let id = wallet(&mut app).deploy_contract(account(), &token, &bytecode, &[]);
+    let error = wallet(&mut app).recv_error().unwrap();
+    assert_eq!((error.op, error.id), (WalletOp::Send, Some(id)));
+}
+
+#[test]
+fn switch_chain_sends_hex_chain_id() {
+    let mock = connected_mock(1);
+    mock.respond("wallet_switchEthereumChain", Value::Null);
+    let mut app = app(&mock);
+
+    wallet(&mut app).switch_chain(42161);
+    let chain = until(&mut app, |app| wallet(app).recv_switch_chain().ok());
+    assert_eq!(chain, 42161);
+    assert_eq!(wallet(&mut app).chain_id, 42161);
+    assert_eq!(
+        params(&mock, "wallet_switchEthereumChain"),
+        [vec![json!({ "chainId": "0xa4b1" })]]
+    );
+}
+
+#[test]
+fn switch_to_unknown_chain_is_unrecognized() {
+    let mock = connected_mock(1);
+    mock.respond_error("wallet_switchEthereumChain", 4902, "Unrecognized chain ID");
+    let mut app = app(&mock);
+
+    wallet(&mut app).switch_chain(42161);
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::SwitchChain);
+    assert!(error.is_unrecognized_chain(), "{error:?}");
+}
+
+fn chain_params() -> AddChainParams {
+    AddChainParams {
+        chain_id: 1337,
+        chain_name: "Game Chain".to_owned(),
+        rpc_urls: vec!["https://rpc.game.example".to_owned()],
+        native_currency: NativeCurrency {
+            name: "Gem".to_owned(),
+            symbol: "GEM".to_owned(),
+            decimals: 18,
+        },
+        block_explorer_urls: None,
+    }
+}
+
+#[test]
+fn add_chain_params_serialize_to_eip3085() {
+    assert_eq!(
+        serde_json::to_value(chain_params()).unwrap(),
+        json!({
+            "chainId": "0x539",
+            "chainName": "Game Chain",
+            "rpcUrls": ["https://rpc.game.example"],
+            "nativeCurrency": { "name": "Gem", "symbol": "GEM", "decimals": 18 },
+        })
+    );
+    let params = AddChainParams {
+        block_explorer_urls: Some(vec!["https://scan.game.example".to_owned()]),
+        ..chain_params()
+    };
+    assert_eq!(
+        serde_json::to_value(params).unwrap()["blockExplorerUrls"],
+        json!(["https://scan.game.example"])
+    );
+}
+
+#[test]
+fn add_chain_sends_params() {
+    let mock = connected_mock(1);
+    mock.respond("wallet_addEthereumChain", Value::Null);
+    let mut app = app(&mock);
+
+    wallet(&mut app).add_chain(chain_params());
+    assert_eq!(
+        until(&mut app, |app| wallet(app).recv_add_chain().ok()),
+        1337
+    );
+    let sent = params(&mock, "wallet_addEthereumChain");
+    assert_eq!(sent, [vec![serde_json::to_value(chain_params()).unwrap()]]);
+}
+
+#[test]
+fn add_chain_rejects_zero_chain_id() {
+    let mock = connected_mock(1);
+    let mut app = app(&mock);
+
+    wallet(&mut app).add_chain(AddChainParams {
+        chain_id: 0,
+        ..chain_params()
+    });
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::AddChain);
+    assert!(params(&mock, "wallet_addEthereumChain").is_empty());
+}
+
+/// Polls every 5ms, at most `max_attempts` times.
+fn fast_watch(max_attempts: u32) -> WatchOptions {
+    WatchOptions {
+        interval: Duration::from_millis(5),
+        max_attempts,
+        confirmations: 0,
+    }
+}
+
+#[test]
+fn watch_transaction_polls_until_mined() {
+    let mock = MockTransport::new();
+    let sent = hash(1);
+    mock.respond_sequence(
+        "eth_getTransactionReceipt",
+        vec![Value::Null, Value::Null, receipt(sent, 9)],
+    );
+    let mut app = app(&mock);
+
+    wallet(&mut app).watch_transaction(sent, fast_watch(10));
+    let mined = until(&mut app, |app| wallet(app).recv_receipt().ok());
+    assert_eq!(mined.hash, sent);
+    assert!(mined.status);
+    assert_eq!(mined.block_number, 9.into());
+    assert_eq!(params(&mock, "eth_getTransactionReceipt").len(), 3);
+}
+
+#[test]
+fn watch_transaction_times_out() {
+    let mock = MockTransport::new();
+    mock.respond("eth_getTransactionReceipt", Value::Null);
+    let mut app = app(&mock);
+
+    wallet(&mut app).watch_transaction(hash(1), fast_watch(3));
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::WatchTransaction);
+    assert!(matches!(error.error, Web3Error::Timeout), "{error:?}");
+    assert!(wallet(&mut app).recv_receipt().is_err());
+}
+
+/// The "Mail" example of the EIP-712 specification.
+const MAIL: &str = r#"{
+    "types": {
+        "EIP712Domain": [
+            { "name": "name", "type": "string" },
+            { "name": "version", "type": "string" },
+            { "name": "chainId", "type": "uint256" },
+            { "name": "verifyingContract", "type": "address" }
+        ],
+        "Person": [
+            { "name": "name", "type": "string" },
+            { "name": "wallet", "type": "address" }
+        ],
+        "Mail": [
+            { "name": "from", "type": "Person" },
+            { "name": "to", "type": "Person" },
+            { "name": "contents", "type": "string" }
+        ]
+    },
+    "primaryType": "Mail",
+    "domain": {
+        "name": "Ether Mail",
+        "version": "1",
+        "chainId": 1,
+        "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+    },
+    "message": {
+        "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+        "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+        "contents": "Hello, Bob!"
+    }
+}"#;
+
+/// The signature of `MAIL` by the spec's `keccak256("cow")` key.
+const MAIL_SIGNATURE: &str = concat!(
+    "0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d",
+    "07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562",
+    "1c",
+);
+
+fn mail() -> TypedData {
+    let domain = Eip712Domain {
+        name: Some("Ether Mail".to_owned()),
+        version: Some("1".to_owned()),
+        chain_id: Some(1),
+        verifying_contract: "cccccccccccccccccccccccccccccccccccccccc".parse().ok(),
+        salt: None,
+    };
+    let message = json!({
+        "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+        "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+        "contents": "Hello, Bob!",
+    });
+    TypedData::new("Mail", domain, message)
+        .with_type("Person", &[("name", "string"), ("wallet", "address")])
+        .with_type(
+            "Mail",
+            &[("from", "Person"), ("to", "Person"), ("contents", "string")],
+        )
+}
+
+#[test]
+fn typed_data_builder_matches_eip712_mail() {
+    let built: Value = serde_json::from_str(&mail().to_json().unwrap()).unwrap();
+    let parsed = TypedData::from_json(MAIL).unwrap();
+    assert_eq!(built, serde_json::to_value(&parsed).unwrap());
+    assert_eq!(
+        parsed.domain.separator(),
+        "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+            .parse()
+            .unwrap()
+    );
+}
+
+#[test]
+fn sign_typed_data_sends_eip712_mail() {
+    let mock = connected_mock(1);
+    mock.respond("eth_signTypedData_v4", json!(MAIL_SIGNATURE));
+    let mut app = app(&mock);
+    let cow: H160 = "cd2a3d9f938e13cd947ec05abc7fe734df8dd826".parse().unwrap();
+
+    let id = wallet(&mut app).sign_typed_data(cow, mail());
+    let (signed, signature) = until(&mut app, |app| {
+        wallet(app).recv_typed_signature_with_id().ok()
+    });
+    assert_eq!(signed, id);
+    assert_eq!(signature, MAIL_SIGNATURE.parse().unwrap());
+
+    let sent = params(&mock, "eth_signTypedData_v4");
+    assert_eq!(sent[0][0], json!(cow));
+    assert_eq!(
+        signed_typed_data(&mock),
+        serde_json::to_value(TypedData::from_json(MAIL).unwrap()).unwrap()
+    );
+}
+
+#[test]
+fn transport_is_built_on_first_use_and_reused() {
+    let mock = connected_mock(1);
+    mock.respond("eth_getBalance", json!("0x2a"));
+    let transport = TransportConfig::Mock(mock.clone());
+    let mut app = app(&mock);
+    let cached = |app: &App| {
+        app.world
+            .non_send_resource::<WalletTasks>()
+            .transports
+            .cached(&transport)
+    };
+    assert_eq!(cached(&app), 0);
+
+    wallet(&mut app).connect();
+    wallet(&mut app).balance(account(), None);
+    let balance = until(&mut app, |app| wallet(app).recv_balance().ok());
+    assert_eq!(balance, 42.into());
+    assert_eq!(cached(&app), 1);
+
+    wallet(&mut app).balance(account(), None);
+    until(&mut app, |app| wallet(app).recv_balance().ok());
+    assert_eq!(cached(&app), 1);
+}
+
+#[test]
+fn missing_provider_is_reported_and_not_cached() {
+    // there is no injected wallet outside the browser
+    let mut app = app_with(WalletConfig::default());
+    let cached = |app: &App| {
+        app.world
+            .non_send_resource::<WalletTasks>()
+            .transports
+            .cached(&TransportConfig::Eip1193)
+    };
+    assert!(!wallet(&mut app).is_provider_available());
+
+    wallet(&mut app).connect();
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::Connect);
+    assert!(
+        matches!(error.error, Web3Error::ProviderUnavailable),
+        "{error:?}"
+    );
+    assert_eq!(cached(&app), 0);
+}
+
+#[test]
+fn requests_can_start_on_other_threads() {
+    let mock = connected_mock(5);
+    let mut app = app(&mock);
+    thread::scope(|scope| {
+        scope.spawn(|| app.world.resource::<EthWallet>().connect());
+    });
+
+    let (_, chain_id) = until(&mut app, |app| wallet(app).recv_account().ok());
+    assert_eq!(chain_id, 5);
+    assert_eq!(wallet(&mut app).accounts, vec![account()]);
+}
+
+#[test]
+fn watch_logs_delivers_new_logs_once() {
+    let mock = MockTransport::new();
+    mock.respond_sequence("eth_blockNumber", vec![json!("0xa"), json!("0xc")]);
+    mock.respond_sequence(
+        "eth_getLogs",
+        vec![json!([log(10, 0)]), json!([log(12, 0)])],
+    );
+    let mut app = app(&mock);
+
+    let interval = Duration::from_millis(5);
+    wallet(&mut app).watch_logs(account(), vec![hash(0xee)], 10, interval);
+    let first = until(&mut app, |app| wallet(app).recv_log().ok());
+    let second = until(&mut app, |app| wallet(app).recv_log().ok());
+    assert_eq!(first.block_number, Some(10.into()));
+    assert_eq!(second.block_number, Some(12.into()));
+
+    // the next poll starts after the last block seen
+    let ranges: Vec<_> = params(&mock, "eth_getLogs")
+        .into_iter()
+        .map(|params| (params[0]["fromBlock"].clone(), params[0]["toBlock"].clone()))
+        .collect();
+    assert_eq!(
+        ranges[..2],
+        [(json!("0xa"), json!("0xa")), (json!("0xb"), json!("0xc"))]
+    );
+    assert_eq!(
+        params(&mock, "eth_getLogs")[0][0]["topics"],
+        json!([hash(0xee)])
+    );
+}
+
+#[test]
+fn chain_changed_updates_wallet_and_fires_event() {
+    let mock = connected_mock(1);
+    let mut app = app_with(config(&mock).emit_events(true));
+    wallet(&mut app).connect();
+    app.update();
+    app.update();
+    assert_eq!(wallet(&mut app).chain_id(), 1);
+
+    // what the `chainChanged` listener forwards, the mock has no EIP-1193 provider
+    wallet(&mut app).chain_changed_tx.try_send(137).unwrap();
+    app.update();
+    let changed: Vec<_> = events::<ChainChanged>(&app)
+        .into_iter()
+        .map(|e| e.0)
+        .collect();
+    assert_eq!(changed, [137]);
+    assert_eq!(wallet(&mut app).chain_id(), 137);
+}
+
+#[test]
+fn account_and_chain_changes_wait_for_recv() {
+    let mock = connected_mock(1);
+    let mut app = app(&mock);
+    let other = H160::repeat_byte(0xbb);
+
+    wallet(&mut app)
+        .accounts_changed_tx
+        .try_send(vec![other])
+        .unwrap();
+    wallet(&mut app).chain_changed_tx.try_send(10).unwrap();
+    app.update();
+    assert!(events::<ChainChanged>(&app).is_empty());
+    assert_eq!(wallet(&mut app).chain_id(), 0);
+
+    assert_eq!(wallet(&mut app).recv_accounts_changed().unwrap(), [other]);
+    assert_eq!(wallet(&mut app).recv_chain_changed().unwrap(), 10);
+    assert_eq!(wallet(&mut app).accounts(), [other]);
+    assert_eq!(wallet(&mut app).chain_id(), 10);
+}
+
+#[test]
+fn results_arriving_after_disconnect_are_dropped() {
+    let mock = connected_mock(1);
+    mock.respond("eth_sign", json!(SIGNATURE));
+    mock.delay("eth_sign", Duration::from_millis(50));
+    let mut app = app_with(config(&mock).emit_events(true));
+    wallet(&mut app).connect();
+    app.update();
+
+    wallet(&mut app).sign_addr(account(), b"hello".to_vec());
+    wallet(&mut app).disconnect();
+    app.update();
+    assert_eq!(events::<WalletDisconnected>(&app).len(), 1);
+    assert!(wallet(&mut app).accounts().is_empty());
+    assert_eq!(wallet(&mut app).chain_id(), 0);
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(200) {
+        app.update();
+        assert!(events::<MessageSigned>(&app).is_empty());
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(wallet(&mut app).recv_signature(), Err(RecvError::Empty));
+    assert!(wallet(&mut app).recv_error().is_err());
+    assert_eq!(params(&mock, "eth_sign").len(), 1);
+}
+
+#[test]
+fn pending_results_are_drained_by_disconnect() {
+    let mock = connected_mock(1);
+    mock.respond("eth_sign", json!(SIGNATURE));
+    let mut app = app(&mock);
+
+    wallet(&mut app).sign_addr(account(), b"hello".to_vec());
+    app.update();
+    wallet(&mut app).disconnect();
+    assert_eq!(wallet(&mut app).recv_signature(), Err(RecvError::Empty));
+}
+
+#[test]
+fn multicall_reports_each_call_on_partial_failure() {
+    use web3::ethabi::{self, ParamType};
+
+    let mock = MockTransport::new();
+    // the second call reverts with `Error("nope")`
+    let revert = [
+        &ethabi::short_signature("Error", &[ParamType::String])[..],
+        &ethabi::encode(&[Token::String("nope".into())]),
+    ]
+    .concat();
+    let results = Token::Array(vec![
+        Token::Tuple(vec![Token::Bool(true), Token::Bytes(vec![1])]),
+        Token::Tuple(vec![Token::Bool(false), Token::Bytes(revert.clone())]),
+    ]);
+    mock.respond("eth_call", json!(Bytes(ethabi::encode(&[results]))));
+    let mut app = app(&mock);
+    let (first, second) = (H160::repeat_byte(1), H160::repeat_byte(2));
+
+    wallet(&mut app).multicall(MULTICALL3, vec![(first, vec![0xaa]), (second, vec![0xbb])]);
+    let results = until(&mut app, |app| wallet(app).recv_multicall().ok());
+    assert_eq!(results, [(true, vec![1]), (false, revert)]);
+
+    let call = &params(&mock, "eth_call")[0][0];
+    assert_eq!(call["to"], json!(MULTICALL3));
+    let data: Bytes = serde_json::from_value(call["data"].clone()).unwrap();
+    let calls = ParamType::Array(Box::new(ParamType::Tuple(vec![
+        ParamType::Address,
+        ParamType::Bytes,
+    ])));
+    let kinds = [ParamType::Bool, calls];
+    assert_eq!(data.0[..4], ethabi::short_signature("tryAggregate", &kinds));
+    let call = |target, data| Token::Tuple(vec![Token::Address(target), Token::Bytes(data)]);
+    assert_eq!(
+        ethabi::decode(&kinds, &data.0[4..]).unwrap(),
+        [
+            Token::Bool(false),
+            Token::Array(vec![call(first, vec![0xaa]), call(second, vec![0xbb])]),
+        ]
+    );
+}
+
+/// The error `sign_addr` reports when the wallet answers with `code`.
+fn sign_error(code: i64) -> Web3OpError {
+    let mock = connected_mock(1);
+    mock.respond_error("eth_sign", code, "injected");
+    let mut app = app(&mock);
+    let id = wallet(&mut app).sign_addr(account(), b"hello".to_vec());
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!((error.op, error.id), (WalletOp::Sign, Some(id)));
+    error
+}
+
+#[test]
+fn provider_error_codes_map_to_variants() {
+    assert!(matches!(
+        sign_error(USER_REJECTED).error,
+        Web3Error::UserRejected
+    ));
+    assert!(matches!(
+        sign_error(UNAUTHORIZED).error,
+        Web3Error::Unauthorized
+    ));
+    assert!(matches!(
+        sign_error(UNSUPPORTED_METHOD).error,
+        Web3Error::UnsupportedMethod
+    ));
+    assert!(matches!(
+        sign_error(DISCONNECTED).error,
+        Web3Error::Disconnected
+    ));
+    assert!(matches!(
+        sign_error(CHAIN_DISCONNECTED).error,
+        Web3Error::Disconnected
+    ));
+    assert!(matches!(
+        sign_error(UNRECOGNIZED_CHAIN).error,
+        Web3Error::UnrecognizedChain
+    ));
+    assert!(matches!(
+        sign_error(REQUEST_PENDING).error,
+        Web3Error::RequestPending
+    ));
+    let other = sign_error(-32000).error;
+    assert!(
+        matches!(&other, Web3Error::Rpc(web3::Error::Rpc(e)) if e.message == "injected"),
+        "{other:?}"
+    );
+}
+
+#[test]
+fn transport_failure_surfaces_as_rpc_error() {
+    // no response configured, the mock transport fails the request
+    let config = config(&MockTransport::new()).operation_policy(short_timeout(0));
+    let mut app = app_with(config);
+    wallet(&mut app).balance(account(), None);
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::Balance);
+    assert!(
+        matches!(error.error, Web3Error::Rpc(web3::Error::Transport(_))),
+        "{error:?}"
+    );
+    assert!(wallet(&mut app).recv_balance().is_err());
+}
+
+#[test]
+fn rejected_sign_and_send_are_user_rejected() {
+    let mock = connected_mock(1);
+    mock.respond_error("eth_sign", USER_REJECTED, "User denied message signature.");
+    mock.respond_error(
+        "eth_sendTransaction",
+        USER_REJECTED,
+        "User denied transaction signature.",
+    );
+    mock.respond("eth_getTransactionCount", json!("0x0"));
+    let mut app = app(&mock);
+
+    let signed = wallet(&mut app).sign_addr(account(), b"hello".to_vec());
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!((error.op, error.id), (WalletOp::Sign, Some(signed)));
+    assert!(error.is_user_rejected(), "{error:?}");
+
+    let sent = wallet(&mut app).send_addr(account(), H160::repeat_byte(0xbb), vec![]);
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!((error.op, error.id), (WalletOp::Send, Some(sent)));
+    assert!(error.is_user_rejected(), "{error:?}");
+
+    assert!(wallet(&mut app).recv_signature().is_err());
+    assert!(wallet(&mut app).recv_transaction().is_err());
+    assert!(wallet(&mut app).sent_transactions().is_empty());
+}
+
+/// Block `number` as returned by `eth_getBlockByNumber`.
+fn block(number: u64) -> Value {
+    json!(Block::<H256> {
+        number: Some(number.into()),
+        hash: Some(hash(number as u8)),
+        timestamp: (1_700_000_000 + number).into(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn watch_blocks_reports_each_block_once() {
+    let mock = MockTransport::new();
+    let heads = ["0xa", "0xa", "0xa", "0xb"].map(|head| json!(head));
+    mock.respond_sequence("eth_blockNumber", heads.to_vec());
+    mock.respond_sequence("eth_getBlockByNumber", vec![block(10), block(11)]);
+    let mut app = app_with(config(&mock).emit_events(true));
+
+    let interval = Duration::from_millis(5);
+    wallet(&mut app).watch_blocks(interval);
+    let mut reader = app.world.resource::<Events<NewBlock>>().get_reader();
+    let mut blocks = vec![];
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(200) {
+        app.update();
+        let events = app.world.resource::<Events<NewBlock>>();
+        blocks.extend(reader.read(events).map(|b| (b.number, b.hash)));
+        thread::sleep(interval);
+    }
+    assert_eq!(blocks, [(10, hash(10)), (11, hash(11))]);
+    assert!(params(&mock, "eth_blockNumber").len() > 4);
+    assert_eq!(params(&mock, "eth_getBlockByNumber").len(), 2);
+}
+
+/// `MAIL` as it is signed: its domain separator and `hashStruct(message)`
+/// from the EIP-712 specification, keccak256 is applied by `recover_signer`.
+#[cfg(feature = "signing")]
+fn mail_signing_input() -> Vec<u8> {
+    let separator = TypedData::from_json(MAIL).unwrap().domain.separator();
+    let struct_hash: H256 = "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        .parse()
+        .unwrap();
+    [&[0x19, 0x01], separator.as_bytes(), struct_hash.as_bytes()].concat()
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn recover_signer_of_eip712_mail() {
+    let cow: H160 = "cd2a3d9f938e13cd947ec05abc7fe734df8dd826".parse().unwrap();
+    let signature: H520 = MAIL_SIGNATURE.parse().unwrap();
+    assert_eq!(
+        recover_signer(&mail_signing_input(), signature).unwrap(),
+        cow
+    );
+
+    // the recovery id form of the same signature
+    let mut raw = signature;
+    raw.0[64] -= 27;
+    assert_eq!(recover_signer(&mail_signing_input(), raw).unwrap(), cow);
+    raw.0[64] = 2;
+    assert!(matches!(
+        recover_signer(&mail_signing_input(), raw),
+        Err(SignatureError::InvalidV(2))
+    ));
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn recover_personal_signer_of_web3js_example() {
+    // `web3.eth.accounts.sign("Some data", key)` from the web3.js documentation
+    let signature: H520 = concat!(
+        "0xb91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd",
+        "6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a029",
+        "1c",
+    )
+    .parse()
+    .unwrap();
+    let signer: H160 = "2c7536e3605d9c16a7a3d7b1898e529396a65c23".parse().unwrap();
+    assert_eq!(
+        recover_personal_signer(b"Some data", signature).unwrap(),
+        signer
+    );
+    // without the prefix, it is someone else's signature
+    assert_ne!(recover_signer(b"Some data", signature).unwrap(), signer);
+}
+
+#[test]
+fn auto_connect_restores_session_at_startup() {
+    let mock = connected_mock(5);
+    let mut app = app_with(config(&mock).auto_connect(true));
+
+    let methods: Vec<_> = mock.requests().into_iter().map(|(m, _)| m).collect();
+    assert_eq!(methods, ["eth_accounts", "eth_chainId"]);
+    assert_eq!(wallet(&mut app).recv_account().unwrap().1, 5);
+    assert_eq!(wallet(&mut app).accounts(), [account()]);
+}
+
+#[test]
+fn no_connect_at_startup_by_default() {
+    let mock = connected_mock(5);
+    let mut app = app(&mock);
+    app.update();
+    assert!(mock.requests().is_empty());
+    assert!(wallet(&mut app).recv_account().is_err());
+}
+
+#[test]
+fn config_carries_into_the_wallet() {
+    let mock = MockTransport::new();
+    let multicall = H160::repeat_byte(0x33);
+    let config = config(&mock)
+        .multicall_address(multicall)
+        .receipt_poll_interval(Duration::from_millis(250));
+    let mut app = app_with(config);
+
+    assert_eq!(
+        wallet(&mut app).watch_options.interval,
+        Duration::from_millis(250)
+    );
+    Erc20::new(H160::repeat_byte(0xcc)).metadata(&wallet(&mut app));
+    app.update();
+    assert_eq!(params(&mock, "eth_call")[0][0]["to"], json!(multicall));
+}
+
+/// The calls of the `index`th `tryAggregate` request, as `(target, selector)`.
+fn multicall_calls(mock: &MockTransport, index: usize) -> Vec<(H160, Vec<u8>)> {
+    let call = &params(mock, "eth_call")[index][0];
+    let data: Bytes = serde_json::from_value(call["data"].clone()).unwrap();
+    let calls = ParamType::Array(Box::new(ParamType::Tuple(vec![
+        ParamType::Address,
+        ParamType::Bytes,
+    ])));
+    let mut tokens = web3::ethabi::decode(&[ParamType::Bool, calls], &data.0[4..]).unwrap();
+    let Some(Token::Array(calls)) = tokens.pop() else {
+        panic!("not a tryAggregate: {tokens:?}")
+    };
+    calls
+        .into_iter()
+        .map(|call| <(H160, Vec<u8>)>::from_token(call).unwrap())
+        .map(|(target, data)| (target, data[..4].to_vec()))
+        .collect()
+}
+
+fn selectors(token: H160, methods: &[&str]) -> Vec<(H160, Vec<u8>)> {
+    let erc20 = Contract::load(ACCOUNT, include_bytes!("abi/erc20.json")).unwrap();
+    methods
+        .iter()
+        .map(|method| (token, erc20.selector(method).unwrap().to_vec()))
+        .collect()
+}
+
+#[test]
+fn metadata_decodes_a_string_symbol_then_reads_the_supply_only() {
+    let mock = MockTransport::new();
+    mock.respond_sequence(
+        "eth_call",
+        vec![
+            multicall_result(vec![
+                Some(Token::String("Dai Stablecoin".into())),
+                Some(Token::String("DAI".into())),
+                Some(Token::Uint(18.into())),
+                Some(Token::Uint(1000.into())),
+            ]),
+            multicall_result(vec![Some(Token::Uint(2000.into()))]),
+        ],
+    );
+    let mut app = app(&mock);
+    let dai = H160::repeat_byte(0xda);
+
+    Erc20::new(dai).metadata(&wallet(&mut app));
+    let metadata = until(&mut app, |app| wallet(app).recv_token_metadata().ok());
+    assert_eq!(
+        (
+            metadata.address,
+            metadata.name.as_str(),
+            metadata.symbol.as_str()
+        ),
+        (dai, "Dai Stablecoin", "DAI")
+    );
+    assert_eq!(
+        (metadata.decimals, metadata.total_supply),
+        (18, 1000.into())
+    );
+    assert_eq!(
+        multicall_calls(&mock, 0),
+        selectors(dai, &["name", "symbol", "decimals", "totalSupply"])
+    );
+
+    Erc20::new(dai).metadata(&wallet(&mut app));
+    let metadata = until(&mut app, |app| wallet(app).recv_token_metadata().ok());
+    assert_eq!((metadata.symbol.as_str(), metadata.decimals), ("DAI", 18));
+    assert_eq!(metadata.total_supply, 2000.into());
+    assert_eq!(multicall_calls(&mock, 1), selectors(dai, &["totalSupply"]));
+}
+
+#[test]
+fn metadata_decodes_a_bytes32_symbol_and_caches_per_chain() {
+    // MKR returns its name and symbol as `bytes32`
+    let bytes32 = |text: &str| {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize(32, 0);
+        Some(Token::FixedBytes(bytes))
+    };
+    let metadata = multicall_result(vec![
+        bytes32("Maker"),
+        bytes32("MKR"),
+        Some(Token::Uint(18.into())),
+        Some(Token::Uint(1000.into())),
+    ]);
+    let mock = MockTransport::new();
+    mock.respond_sequence("eth_call", vec![metadata.clone(), metadata]);
+    let mut app = app(&mock);
+    let mkr = H160::repeat_byte(0x9f);
+
+    Erc20::new(mkr).metadata(&wallet(&mut app));
+    let fetched = until(&mut app, |app| wallet(app).recv_token_metadata().ok());
+    assert_eq!(
+        (fetched.name.as_str(), fetched.symbol.as_str()),
+        ("Maker", "MKR")
+    );
+    assert_eq!(fetched.decimals, 18);
+
+    // the same address on another chain is fetched in full
+    wallet(&mut app).chain_id = 5;
+    Erc20::new(mkr).metadata(&wallet(&mut app));
+    let fetched = until(&mut app, |app| wallet(app).recv_token_metadata().ok());
+    assert_eq!(fetched.symbol, "MKR");
+    assert_eq!(
+        multicall_calls(&mock, 1),
+        selectors(mkr, &["name", "symbol", "decimals", "totalSupply"])
+    );
+}
+
+/// The example message of the EIP-4361 specification.
+const SIWE: &str = "service.invalid wants you to sign in with your Ethereum account:
+0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2
+
+I accept the ServiceOrg Terms of Service: https://service.invalid/tos
+
+URI: https://service.invalid/login
+Version: 1
+Chain ID: 1
+Nonce: 32891756
+Issued At: 2021-09-30T16:25:24Z
+Resources:
+- ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/
+- https://example.com/my-web2-claim.json";
+
+fn siwe(address: H160) -> SiweMessage {
+    SiweMessage::new(
+        "service.invalid",
+        address,
+        "https://service.invalid/login",
+        1,
+        "32891756",
+        "2021-09-30T16:25:24Z",
+    )
+    .with_statement("I accept the ServiceOrg Terms of Service: https://service.invalid/tos")
+    .with_resource("ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/")
+    .with_resource("https://example.com/my-web2-claim.json")
+}
+
+fn weth() -> H160 {
+    "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".parse().unwrap()
+}
+
+#[test]
+fn siwe_renders_the_eip4361_example() {
+    assert_eq!(siwe(weth()).to_string(), SIWE);
+    assert_eq!(SiweMessage::parse(SIWE).unwrap(), siwe(weth()));
+}
+
+#[test]
+fn sign_siwe_personal_signs_the_canonical_string() {
+    let mock = connected_mock(1);
+    mock.respond("personal_sign", json!(SIGNATURE));
+    let mut app = app(&mock);
+
+    wallet(&mut app).sign_siwe(weth(), siwe(weth()));
+    until(&mut app, |app| wallet(app).recv_personal_signature().ok());
+    let sent = params(&mock, "personal_sign");
+    assert_eq!(sent[0], [json!(Bytes(SIWE.into())), json!(weth())]);
+}
+
+#[test]
+fn siwe_rejects_an_address_without_checksum() {
+    let lowercase = SIWE.replace(
+        "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+    );
+    let error = SiweMessage::parse(&lowercase).unwrap_err();
+    assert!(
+        matches!(&error, SiweError::InvalidChecksum(address) if address.starts_with("0xc02a")),
+        "{error:?}"
+    );
+}
+
+/// `message` signed by the example key of the web3.js documentation, whose
+/// address it must be for.
+#[cfg(feature = "signing")]
+fn siwe_signed(message: impl FnOnce(H160) -> SiweMessage) -> (SiweMessage, H520) {
+    use web3::signing::{Key, SecretKey, SecretKeyRef};
+
+    let key: SecretKey = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        .parse()
+        .unwrap();
+    let key = SecretKeyRef::new(&key);
+    let message = message(key.address());
+    let hash = web3::signing::hash_message(message.to_string());
+    let signed = key.sign_message(hash.as_bytes()).unwrap();
+    let signature = H520::from_slice(
+        &[
+            signed.r.as_bytes(),
+            signed.s.as_bytes(),
+            &[signed.v as u8 + 27],
+        ]
+        .concat(),
+    );
+    (message, signature)
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn siwe_verifies_the_signer() {
+    let (message, signature) = siwe_signed(siwe);
+
+    message.verify(signature, &VerifyOpts::default()).unwrap();
+    // another address, so another message, the signature recovers to someone else
+    let error = siwe(weth())
+        .verify(signature, &VerifyOpts::default())
+        .unwrap_err();
+    assert!(matches!(error, SiweError::SignerMismatch(_)), "{error:?}");
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn siwe_verifies_domain_nonce_and_validity() {
+    let (message, signature) = siwe_signed(|address| {
+        siwe(address)
+            .with_not_before("2021-09-30T18:25:24+02:00")
+            .with_expiration_time("2021-09-30T17:25:24.500Z")
+    });
+    // 2021-09-30T16:25:24Z
+    let issued_at = 1_633_019_124;
+    let opts = VerifyOpts {
+        domain: Some("service.invalid".to_owned()),
+        nonce: Some("32891756".to_owned()),
+        now: Some(issued_at + 60),
+    };
+    message.verify(signature, &opts).unwrap();
+
+    let verify = |opts: VerifyOpts| message.verify(signature, &opts).unwrap_err();
+    let error = verify(VerifyOpts {
+        domain: Some("attacker.invalid".to_owned()),
+        ..opts.clone()
+    });
+    assert!(matches!(error, SiweError::DomainMismatch(_)), "{error:?}");
+    let error = verify(VerifyOpts {
+        nonce: Some("other".to_owned()),
+        ..opts.clone()
+    });
+    assert!(matches!(error, SiweError::NonceMismatch(_)), "{error:?}");
+    // not before is 16:25:24 in UTC
+    let error = verify(VerifyOpts {
+        now: Some(issued_at - 1),
+        ..opts.clone()
+    });
+    assert!(matches!(error, SiweError::NotYetValid), "{error:?}");
+    let error = verify(VerifyOpts {
+        now: Some(issued_at + 3600),
+        ..opts.clone()
+    });
+    assert!(matches!(error, SiweError::Expired), "{error:?}");
+    assert!(message
+        .verify(
+            signature,
+            &VerifyOpts {
+                now: Some(issued_at + 3599),
+                ..opts
+            }
+        )
+        .is_ok());
+}
+
+fn confirmations(confirmations: u64) -> WatchOptions {
+    WatchOptions {
+        confirmations,
+        ..fast_watch(20)
+    }
+}
+
+#[test]
+fn confirmed_once_head_is_deep_enough() {
+    let mock = MockTransport::new();
+    let sent = hash(1);
+    mock.respond("eth_getTransactionReceipt", receipt(sent, 10));
+    let heads = ["0xa", "0xb", "0xc"].map(|head| json!(head));
+    mock.respond_sequence("eth_blockNumber", heads.to_vec());
+    let mut app = app(&mock);
+
+    wallet(&mut app).watch_transaction(sent, confirmations(2));
+    let confirmed = until(&mut app, |app| wallet(app).recv_confirmed().ok());
+    assert_eq!((confirmed.hash, confirmed.block_number), (sent, 10.into()));
+    assert_eq!(params(&mock, "eth_blockNumber").len(), 3);
+    assert!(wallet(&mut app).recv_receipt().is_err());
+}
+
+#[test]
+fn receipt_disappearing_is_a_reorg() {
+    let mock = MockTransport::new();
+    let sent = hash(1);
+    mock.respond_sequence(
+        "eth_getTransactionReceipt",
+        vec![receipt(sent, 10), Value::Null],
+    );
+    mock.respond("eth_blockNumber", json!("0xa"));
+    let mut app = app(&mock);
+
+    wallet(&mut app).watch_transaction(sent, confirmations(2));
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::WatchTransaction);
+    assert!(
+        matches!(error.error, Web3Error::Reorged(h) if h == sent),
+        "{error:?}"
+    );
+    assert!(wallet(&mut app).recv_confirmed().is_err());
+}
+
+#[test]
+fn receipt_moving_to_another_block_is_a_reorg() {
+    let mock = MockTransport::new();
+    let sent = hash(1);
+    mock.respond_sequence(
+        "eth_getTransactionReceipt",
+        vec![receipt(sent, 10), receipt(sent, 11)],
+    );
+    mock.respond("eth_blockNumber", json!("0xa"));
+    let mut app = app(&mock);
+
+    wallet(&mut app).watch_transaction(sent, confirmations(2));
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert!(
+        matches!(error.error, Web3Error::Reorged(h) if h == sent),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn namehash_matches_eip137() {
+    assert_eq!(namehash(""), H256::zero());
+    assert_eq!(
+        namehash("eth"),
+        "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+            .parse()
+            .unwrap()
+    );
+    assert_eq!(
+        namehash("foo.eth"),
+        "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+            .parse()
+            .unwrap()
+    );
+    assert_eq!(namehash("Foo.ETH"), namehash("foo.eth"));
+}
+
+/// The return data of a call returning `token`.
+fn returns(token: Token) -> Value {
+    json!(Bytes(web3::ethabi::encode(&[token])))
+}
+
+/// The `(to, selector, node)` of each `eth_call` `mock` received.
+fn node_calls(mock: &MockTransport) -> Vec<(Value, Vec<u8>, H256)> {
+    params(mock, "eth_call")
+        .into_iter()
+        .map(|params| {
+            let data: Bytes = serde_json::from_value(params[0]["data"].clone()).unwrap();
+            let node = H256::from_slice(&data.0[4..36]);
+            (params[0]["to"].clone(), data.0[..4].to_vec(), node)
+        })
+        .collect()
+}
+
+fn selector(method: &str) -> Vec<u8> {
+    use web3::ethabi::ParamType;
+    web3::ethabi::short_signature(method, &[ParamType::FixedBytes(32)]).to_vec()
+}
+
+#[test]
+fn resolve_ens_asks_the_registry_then_the_resolver() {
+    let mock = MockTransport::new();
+    let (resolver, owner) = (H160::repeat_byte(0x7e), H160::repeat_byte(0xd8));
+    mock.respond_sequence(
+        "eth_call",
+        vec![
+            returns(Token::Address(resolver)),
+            returns(Token::Address(owner)),
+        ],
+    );
+    let mut app = app(&mock);
+
+    wallet(&mut app).resolve_ens("vitalik.eth".to_owned());
+    let resolved = until(&mut app, |app| wallet(app).recv_ens_address().ok());
+    assert_eq!(resolved, ("vitalik.eth".to_owned(), Some(owner)));
+    let node = namehash("vitalik.eth");
+    assert_eq!(
+        node_calls(&mock),
+        [
+            (json!(ENS_REGISTRY), selector("resolver"), node),
+            (json!(resolver), selector("addr"), node),
+        ]
+    );
+}
+
+#[test]
+fn resolve_ens_without_registry_is_none() {
+    let mock = MockTransport::new();
+    // no code at the registry address
+    mock.respond("eth_call", json!("0x"));
+    let mut app = app(&mock);
+
+    wallet(&mut app).resolve_ens("vitalik.eth".to_owned());
+    let resolved = until(&mut app, |app| wallet(app).recv_ens_address().ok());
+    assert_eq!(resolved, ("vitalik.eth".to_owned(), None));
+    assert_eq!(params(&mock, "eth_call").len(), 1);
+}
+
+#[test]
+fn reverse_resolve_checks_the_forward_record() {
+    let mock = MockTransport::new();
+    let (resolver, owner) = (H160::repeat_byte(0x7e), H160::repeat_byte(0xd8));
+    mock.respond_sequence(
+        "eth_call",
+        vec![
+            returns(Token::Address(resolver)),
+            returns(Token::String("vitalik.eth".into())),
+            returns(Token::Address(resolver)),
+            returns(Token::Address(owner)),
+        ],
+    );
+    let mut app = app(&mock);
+
+    wallet(&mut app).reverse_resolve(owner);
+    let resolved = until(&mut app, |app| wallet(app).recv_ens_name().ok());
+    assert_eq!(resolved, (owner, Some("vitalik.eth".to_owned())));
+    let reverse = namehash(&format!("{}.addr.reverse", "d8".repeat(20)));
+    let calls = node_calls(&mock);
+    assert_eq!(
+        calls[0],
+        (json!(ENS_REGISTRY), selector("resolver"), reverse)
+    );
+    assert_eq!(calls[1], (json!(resolver), selector("name"), reverse));
+    assert_eq!(calls[3].2, namehash("vitalik.eth"));
+}
+
+#[test]
+fn reverse_name_resolving_elsewhere_is_ignored() {
+    let mock = MockTransport::new();
+    let resolver = H160::repeat_byte(0x7e);
+    mock.respond_sequence(
+        "eth_call",
+        vec![
+            returns(Token::Address(resolver)),
+            returns(Token::String("vitalik.eth".into())),
+            returns(Token::Address(resolver)),
+            returns(Token::Address(H160::repeat_byte(0x01))),
+        ],
+    );
+    let mut app = app(&mock);
+
+    let claimer = H160::repeat_byte(0xd8);
+    wallet(&mut app).reverse_resolve(claimer);
+    let resolved = until(&mut app, |app| wallet(app).recv_ens_name().ok());
+    assert_eq!(resolved, (claimer, None));
+}
+
+#[test]
+fn suggest_fees_from_fee_history() {
+    let mock = MockTransport::new();
+    let gwei = |n: u64| format!("{:#x}", n * 1_000_000_000);
+    mock.respond(
+        "eth_feeHistory",
+        json!({
+            "oldestBlock": "0x64",
+            // one more than the blocks, the base fee of the next one
+            "baseFeePerGas": [gwei(10), gwei(11), gwei(12), gwei(13)],
+            "gasUsedRatio": [0.5, 0.9, 0.7],
+            "reward": [[gwei(3)], [gwei(1)], [gwei(2)]],
+        }),
+    );
+    let mut app = app(&mock);
+
+    wallet(&mut app).suggest_fees();
+    let fees = until(&mut app, |app| wallet(app).recv_fees().ok());
+    let gwei = |n: u64| U256::from(n) * 1_000_000_000;
+    assert_eq!(fees.base_fee, gwei(13));
+    assert_eq!(fees.max_priority_fee, gwei(2));
+    assert_eq!(fees.max_fee, gwei(28));
+    let options = fees.options();
+    assert_eq!(
+        (options.max_fee_per_gas, options.max_priority_fee_per_gas),
+        (Some(gwei(28)), Some(gwei(2)))
+    );
+    assert_eq!(
+        params(&mock, "eth_feeHistory"),
+        [vec![json!("0xa"), json!("latest"), json!([50.0])]]
+    );
+}
+
+#[test]
+fn suggest_fees_without_eip1559_is_unsupported() {
+    let mock = MockTransport::new();
+    mock.respond(
+        "eth_feeHistory",
+        json!({ "oldestBlock": "0x64", "baseFeePerGas": [], "gasUsedRatio": [] }),
+    );
+    let mut app = app(&mock);
+
+    wallet(&mut app).suggest_fees();
+    let error = until(&mut app, |app| wallet(app).recv_error().ok());
+    assert_eq!(error.op, WalletOp::SuggestFees);
+    assert!(
+        matches!(error.error, Web3Error::UnsupportedMethod),
+        "{error:?}"
+    );
+}
+
+/// An ERC-721 excerpt, `safeTransferFrom` is overloaded.
+const NFT_ABI: &[u8] = br#"[
+    {
+        "type": "function",
+        "name": "safeTransferFrom",
+        "inputs": [
+            { "name": "from", "type": "address" },
+            { "name": "to", "type": "address" },
+            { "name": "tokenId", "type": "uint256" }
+        ],
+        "outputs": [],
+        "stateMutability": "nonpayable"
+    },
+    {
+        "type": "function",
+        "name": "safeTransferFrom",
+        "inputs": [
+            { "name": "from", "type": "address" },
+            { "name": "to", "type": "address" },
+            { "name": "tokenId", "type": "uint256" },
+            { "name": "data", "type": "bytes" }
+        ],
+        "outputs": [],
+        "stateMutability": "nonpayable"
+    },
+    {
+        "type": "function",
+        "name": "mintBatch",
+        "inputs": [{
+            "name": "mints",
+            "type": "tuple[]",
+            "components": [
+                { "name": "to", "type": "address" },
+                { "name": "tokenId", "type": "uint256" }
+            ]
+        }],
+        "outputs": [],
+        "stateMutability": "nonpayable"
+    },
+    {
+        "type": "event",
+        "name": "Transfer",
+        "inputs": [
+            { "name": "from", "type": "address", "indexed": true },
+            { "name": "to", "type": "address", "indexed": true },
+            { "name": "tokenId", "type": "uint256", "indexed": true }
+        ],
+        "anonymous": false
+    },
+    {
+        "type": "event",
+        "name": "Approval",
+        "inputs": [
+            { "name": "owner", "type": "address", "indexed": true },
+            { "name": "approved", "type": "address", "indexed": true },
+            { "name": "tokenId", "type": "uint256", "indexed": true }
+        ],
+        "anonymous": false
+    }
+]"#;
+
+#[test]
+fn signatures_list_overloads_and_events() {
+    let contract = Contract::load(ACCOUNT, NFT_ABI).unwrap();
+
+    let mut signatures = contract.signatures();
+    signatures.sort();
+    assert_eq!(
+        signatures,
+        [
+            "mintBatch((address,uint256)[])",
+            "safeTransferFrom(address,address,uint256)",
+            "safeTransferFrom(address,address,uint256,bytes)",
+        ]
+    );
+    let mut functions = contract.functions();
+    functions.sort();
+    assert_eq!(functions, ["mintBatch", "safeTransferFrom"]);
+    let mut events = contract.events();
+    events.sort();
+    assert_eq!(events, ["Approval", "Transfer"]);
+
+    assert_eq!(
+        contract.signature("mintBatch").unwrap(),
+        "mintBatch((address,uint256)[])"
+    );
+    // the selector is derived from the canonical signature
+    let hash = web3::signing::keccak256(b"mintBatch((address,uint256)[])");
+    assert_eq!(contract.selector("mintBatch").unwrap(), hash[..4]);
+    assert!(contract
+        .signature("safeTransferFrom")
+        .unwrap()
+        .starts_with("safeTransferFrom("));
+    assert!(matches!(
+        contract.signature("burn"),
+        Err(ContractError::UnknownMethod { .. })
+    ));
+}
+
+#[test]
+fn names_and_function_signature_with_overloads() {
+    let contract = Contract::load(ACCOUNT, NFT_ABI).unwrap();
+
+    // overloads are listed once
+    assert_eq!(contract.function_names(), ["mintBatch", "safeTransferFrom"]);
+    assert_eq!(contract.event_names(), ["Approval", "Transfer"]);
+
+    // the first overload of the abi
+    assert_eq!(
+        contract.function_signature("safeTransferFrom").as_deref(),
+        Some("safeTransferFrom(address,address,uint256)")
+    );
+    let signature = contract.function_signature("safeTransferFrom").unwrap();
+    assert!(contract.signatures().contains(&signature));
+    assert_eq!(
+        contract.function_signature("mintBatch").as_deref(),
+        Some("mintBatch((address,uint256)[])")
+    );
+    assert_eq!(contract.function_signature("burn"), None);
+    // events are not functions
+    assert_eq!(contract.function_signature("Transfer"), None);
+}
@@ -0,0 +1,423 @@
+use js_sys::Reflect;
+use jsonrpc_core::{Call, ErrorCode, Params, Value};
+use std::{
+    cell::RefCell,
+    fmt,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web3::{
+    error::TransportError,
+    futures::future::LocalBoxFuture,
+    helpers,
+    transports::eip_1193::{self, Eip1193},
+    RequestId, Transport, Web3,
+};
+
+#[cfg(feature = "http")]
+use web3::{signing::SecretKey, transports::Http};
+
+#[cfg(any(test, feature = "testing"))]
+use crate::MockTransport;
+
+use crate::Web3Error;
+
+/// Where `EthWallet` sends its requests.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TransportConfig {
+    /// The wallet injected in the browser (e.g. MetaMask).
+    #[default]
+    Eip1193,
+    /// The EIP-6963 wallet announced with this reverse-DNS name.
+    Eip6963 { rdns: String },
+    /// A JSON-RPC node over HTTP. Reads work without a wallet, transactions and
+    /// messages are signed with `local_key` if set, otherwise by the node.
+    #[cfg(feature = "http")]
+    Http {
+        url: String,
+        local_key: Option<SecretKey>,
+    },
+    /// The session established by `WalletConnect::connect`.
+    #[cfg(feature = "walletconnect")]
+    WalletConnect,
+    /// A transport of your own, see `Web3Transport`.
+    Custom(CustomTransport),
+    /// Canned responses, for tests.
+    #[cfg(any(test, feature = "testing"))]
+    Mock(MockTransport),
+}
+
+/// A JSON-RPC transport to plug into `TransportConfig::Custom`, e.g. a node
+/// behind custom authentication or a recorded session. Implemented for every
+/// `web3::Transport` that can be shared between threads.
+pub trait Web3Transport: fmt::Debug + Send + Sync + 'static {
+    /// Send `method` with `params`, resolving to the result.
+    fn execute(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> LocalBoxFuture<'static, web3::Result<Value>>;
+}
+
+impl<T> Web3Transport for T
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: 'static,
+{
+    fn execute(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> LocalBoxFuture<'static, web3::Result<Value>> {
+        Box::pin(Transport::execute(self, method, params))
+    }
+}
+
+/// The transport of `TransportConfig::Custom`, compared by identity.
+#[derive(Clone, Debug)]
+pub struct CustomTransport(Arc<dyn Web3Transport>);
+
+impl CustomTransport {
+    pub fn new(transport: impl Web3Transport) -> Self {
+        CustomTransport(Arc::new(transport))
+    }
+}
+
+impl From<Arc<dyn Web3Transport>> for CustomTransport {
+    fn from(transport: Arc<dyn Web3Transport>) -> Self {
+        CustomTransport(transport)
+    }
+}
+
+impl PartialEq for CustomTransport {
+    fn eq(&self, other: &Self) -> bool {
+        // the vtable of the same transport may differ across codegen units
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+/// The transports built so far, shared by the tasks `WalletTasks` runs. They
+/// are not `Send`, so they stay on the thread running the tasks rather than
+/// in the `EthWallet` resource.
+#[derive(Clone, Default)]
+pub(crate) struct Transports(Rc<RefCell<Vec<CachedTransport>>>);
+
+struct CachedTransport {
+    config: TransportConfig,
+    /// The provider it was built around, see `TransportConfig::replaceable_provider`.
+    provider: Option<eip_1193::Provider>,
+    web3: Web3<WalletTransport>,
+}
+
+impl Transports {
+    /// The transport for `config`, built on first use and reused afterwards.
+    /// It is built again once the provider behind `config` was replaced.
+    /// A failure (e.g. no injected provider) is not cached, the next call retries.
+    pub(crate) fn web3(
+        &self,
+        config: &TransportConfig,
+    ) -> Result<Web3<WalletTransport>, Web3Error> {
+        let provider = config.replaceable_provider();
+        let mut cache = self.0.borrow_mut();
+        if let Some(at) = cache.iter().position(|cached| cached.config == *config) {
+            if same_provider(&cache[at].provider, &provider) {
+                return Ok(cache[at].web3.clone());
+            }
+            cache.remove(at);
+        }
+
+        let web3 = config.build()?;
+        cache.push(CachedTransport {
+            config: config.clone(),
+            provider,
+            web3: web3.clone(),
+        });
+        Ok(web3)
+    }
+
+    /// How many transports are cached for `config`.
+    #[cfg(test)]
+    pub(crate) fn cached(&self, config: &TransportConfig) -> usize {
+        self.0
+            .borrow()
+            .iter()
+            .filter(|cached| cached.config == *config)
+            .count()
+    }
+}
+
+fn same_provider(a: &Option<eip_1193::Provider>, b: &Option<eip_1193::Provider>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let (a, b): (&JsValue, &JsValue) = (a.as_ref(), b.as_ref());
+            a == b
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl TransportConfig {
+    /// `TransportConfig::Custom` sending through `transport`.
+    pub fn custom(transport: impl Web3Transport) -> Self {
+        TransportConfig::Custom(CustomTransport::new(transport))
+    }
+
+    /// Whether the transport for this config can be set up, without caching it.
+    pub(crate) fn is_available(&self) -> bool {
+        self.build().is_ok()
+    }
+
+    /// The provider behind this config when it can be replaced once the
+    /// transport is built: an EIP-6963 wallet announcing itself again, or a
+    /// new WalletConnect session.
+    fn replaceable_provider(&self) -> Option<eip_1193::Provider> {
+        match self {
+            TransportConfig::Eip6963 { .. } => self.provider(),
+            #[cfg(feature = "walletconnect")]
+            TransportConfig::WalletConnect => self.provider(),
+            _ => None,
+        }
+    }
+
+    fn build(&self) -> Result<Web3<WalletTransport>, Web3Error> {
+        let transport = match self {
+            #[cfg(feature = "http")]
+            TransportConfig::Http { url, .. } => WalletTransport::Http(Http::new(url)?),
+            TransportConfig::Custom(custom) => WalletTransport::Custom(custom.0.clone()),
+            #[cfg(any(test, feature = "testing"))]
+            TransportConfig::Mock(mock) => WalletTransport::Custom(Arc::new(mock.clone())),
+            _ => {
+                let provider = self.provider().ok_or(Web3Error::ProviderUnavailable)?;
+                WalletTransport::Eip1193(Eip1193::new(provider))
+            }
+        };
+        Ok(Web3::new(transport))
+    }
+
+    /// The EIP-1193 provider behind this config, `None` for a node or when
+    /// the wallet is missing.
+    pub(crate) fn provider(&self) -> Option<eip_1193::Provider> {
+        match self {
+            // there is no `window.ethereum` to look up outside the browser
+            TransportConfig::Eip1193 if cfg!(target_arch = "wasm32") => {
+                eip_1193::Provider::default().ok().flatten()
+            }
+            TransportConfig::Eip1193 => None,
+            TransportConfig::Eip6963 { rdns } => crate::eip6963::provider(rdns),
+            #[cfg(feature = "http")]
+            TransportConfig::Http { .. } => None,
+            #[cfg(feature = "walletconnect")]
+            TransportConfig::WalletConnect => crate::walletconnect::provider(),
+            TransportConfig::Custom(_) => None,
+            #[cfg(any(test, feature = "testing"))]
+            TransportConfig::Mock(_) => None,
+        }
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn local_key(&self) -> Option<SecretKey> {
+        match self {
+            TransportConfig::Http { local_key, .. } => *local_key,
+            _ => None,
+        }
+    }
+}
+
+/// Send `method` with `params` as a single object instead of the positional
+/// array `Transport` always sends, for the wallet methods that require it
+/// (e.g. `wallet_watchAsset`).
+pub(crate) async fn request_with_object(
+    provider: eip_1193::Provider,
+    method: &str,
+    params: Value,
+) -> Result<Value, Web3Error> {
+    let provider: &JsValue = provider.as_ref();
+    let args = js_sys::Object::new();
+    Reflect::set(&args, &"method".into(), &method.into()).map_err(js_error)?;
+    let params = js_sys::JSON::parse(&params.to_string()).map_err(js_error)?;
+    Reflect::set(&args, &"params".into(), &params).map_err(js_error)?;
+
+    let request: js_sys::Function = Reflect::get(provider, &"request".into())
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    let promise = request.call1(provider, &args).map_err(js_error)?;
+    let result = JsFuture::from(js_sys::Promise::from(promise))
+        .await
+        .map_err(js_error)?;
+    if result.is_undefined() {
+        return Ok(Value::Null);
+    }
+    let result = js_sys::JSON::stringify(&result).map_err(js_error)?;
+    Ok(serde_json::from_str(&String::from(result))?)
+}
+
+/// A JS exception or EIP-1193 error (`{ code, message }`), keeping the code
+/// so e.g. 4001 still maps to `UserRejected`.
+pub(crate) fn js_error(e: JsValue) -> Web3Error {
+    let code = Reflect::get(&e, &"code".into())
+        .ok()
+        .and_then(|c| c.as_f64());
+    let message = Reflect::get(&e, &"message".into())
+        .ok()
+        .and_then(|m| m.as_string())
+        .unwrap_or_else(|| format!("{:?}", e));
+    match code {
+        Some(code) => web3::Error::Rpc(jsonrpc_core::Error {
+            code: ErrorCode::from(code as i64),
+            message,
+            data: None,
+        })
+        .into(),
+        None => Web3Error::Rpc(web3::Error::Transport(TransportError::Message(message))),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum WalletTransport {
+    Eip1193(Eip1193),
+    #[cfg(feature = "http")]
+    Http(Http),
+    Custom(Arc<dyn Web3Transport>),
+}
+
+impl WalletTransport {
+    pub(crate) fn eip1193(&self) -> Option<Eip1193> {
+        match self {
+            WalletTransport::Eip1193(t) => Some(t.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The ids of the requests sent through a `Web3Transport`, which only sees
+/// their method and params.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+impl Transport for WalletTransport {
+    type Out = LocalBoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        match self {
+            WalletTransport::Eip1193(t) => t.prepare(method, params),
+            #[cfg(feature = "http")]
+            WalletTransport::Http(t) => t.prepare(method, params),
+            WalletTransport::Custom(_) => {
+                let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+                (id, helpers::build_request(id, method, params))
+            }
+        }
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        match self {
+            WalletTransport::Eip1193(t) => t.send(id, request),
+            #[cfg(feature = "http")]
+            WalletTransport::Http(t) => Box::pin(t.send(id, request)),
+            WalletTransport::Custom(t) => {
+                let Call::MethodCall(call) = request else {
+                    let e = TransportError::Message("unsupported request".to_owned());
+                    return Box::pin(async move { Err(web3::Error::Transport(e)) });
+                };
+                let params = match call.params {
+                    Params::Array(params) => params,
+                    Params::Map(map) => vec![Value::Object(map)],
+                    Params::None => vec![],
+                };
+                t.execute(&call.method, params)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+pub(crate) use local::send_local;
+
+#[cfg(feature = "http")]
+mod local {
+    use async_channel::Sender;
+    use web3::{
+        signing::{Key, SecretKey, SecretKeyRef},
+        types::{CallRequest, TransactionParameters, H160, H256, H520},
+        Web3,
+    };
+
+    use super::WalletTransport;
+    use crate::{EthWallet, RequestId, SendOptions, WalletOp, Web3Error};
+
+    impl EthWallet {
+        pub(crate) fn connect_local(&self, key: SecretKey) {
+            let transport = self.transport.clone();
+            let tx = self.account_tx.clone();
+            self.spawn(WalletOp::Connect, tx, move |transports| async move {
+                let web3 = transports.web3(&transport)?;
+                let chain = web3.eth().chain_id().await?;
+                Ok(Some((
+                    vec![SecretKeyRef::new(&key).address()],
+                    chain.as_u64(),
+                )))
+            });
+        }
+
+        pub(crate) fn sign_local(
+            &self,
+            key: SecretKey,
+            op: WalletOp,
+            id: RequestId,
+            tx: Sender<(RequestId, H520)>,
+            msg: Vec<u8>,
+        ) {
+            let transport = self.transport.clone();
+            self.spawn_request(op, id, tx, move |transports| async move {
+                let signed = transports.web3(&transport)?.accounts().sign(msg, &key);
+                Ok(Some((id, H520::from_slice(&signed.signature.0))))
+            });
+        }
+    }
+
+    /// Sign the transaction with `key` and send it raw.
+    pub(crate) async fn send_local(
+        web3: &Web3<WalletTransport>,
+        key: SecretKey,
+        to: Option<H160>,
+        data: Vec<u8>,
+        options: SendOptions,
+    ) -> Result<H256, Web3Error> {
+        let gas = match options.gas {
+            Some(gas) => gas,
+            None => {
+                let call = CallRequest {
+                    from: Some(SecretKeyRef::new(&key).address()),
+                    to,
+                    data: Some(data.clone().into()),
+                    value: options.value,
+                    ..Default::default()
+                };
+                web3.eth().estimate_gas(call, None).await?
+            }
+        };
+        let params = TransactionParameters {
+            nonce: options.nonce,
+            to,
+            gas,
+            // for EIP-1559 transactions `gas_price` is the max fee per gas
+            gas_price: options.max_fee_per_gas.or(options.gas_price),
+            value: options.value.unwrap_or_default(),
+            data: data.into(),
+            transaction_type: options.is_eip1559().then(|| 2.into()),
+            max_priority_fee_per_gas: options.max_priority_fee_per_gas,
+            ..Default::default()
+        };
+        let signed = web3.accounts().sign_transaction(params, &key).await?;
+        Ok(web3
+            .eth()
+            .send_raw_transaction(signed.raw_transaction)
+            .await?)
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use web3::{
+    signing::keccak256,
+    types::{H160, H256, U256},
+};
+
+/// EIP-712 typed data, as sent to `eth_signTypedData_v4`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<TypedDataField>>,
+    pub primary_type: String,
+    pub domain: Eip712Domain,
+    pub message: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypedDataField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Eip712Domain {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verifying_contract: Option<H160>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salt: Option<H256>,
+}
+
+impl Eip712Domain {
+    /// The `EIP712Domain` type entry matching the fields which are set.
+    pub fn fields(&self) -> Vec<TypedDataField> {
+        let mut fields = vec![];
+        if self.name.is_some() {
+            fields.push(TypedDataField::new("name", "string"));
+        }
+        if self.version.is_some() {
+            fields.push(TypedDataField::new("version", "string"));
+        }
+        if self.chain_id.is_some() {
+            fields.push(TypedDataField::new("chainId", "uint256"));
+        }
+        if self.verifying_contract.is_some() {
+            fields.push(TypedDataField::new("verifyingContract", "address"));
+        }
+        if self.salt.is_some() {
+            fields.push(TypedDataField::new("salt", "bytes32"));
+        }
+        fields
+    }
+
+    /// The EIP-712 domain separator, e.g. to compare with a contract's
+    /// `DOMAIN_SEPARATOR()`.
+    ///
+    /// ```
+    /// use bevy_web3::Eip712Domain;
+    ///
+    /// // USDC on mainnet
+    /// let domain = Eip712Domain {
+    ///     name: Some("USD Coin".into()),
+    ///     version: Some("2".into()),
+    ///     chain_id: Some(1),
+    ///     verifying_contract: "a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".parse().ok(),
+    ///     salt: None,
+    /// };
+    /// let expected = "06c37168a7db5138defc7866392bb87a741f9b3d104deb5094588ce041cae335";
+    /// assert_eq!(domain.separator(), expected.parse().unwrap());
+    /// ```
+    pub fn separator(&self) -> H256 {
+        let fields = self.fields();
+        let members: Vec<_> = fields
+            .iter()
+            .map(|f| format!("{} {}", f.kind, f.name))
+            .collect();
+        let type_hash = keccak256(format!("EIP712Domain({})", members.join(",")).as_bytes());
+
+        let mut encoded = type_hash.to_vec();
+        if let Some(name) = &self.name {
+            encoded.extend(keccak256(name.as_bytes()));
+        }
+        if let Some(version) = &self.version {
+            encoded.extend(keccak256(version.as_bytes()));
+        }
+        if let Some(chain_id) = self.chain_id {
+            let mut word = [0u8; 32];
+            U256::from(chain_id).to_big_endian(&mut word);
+            encoded.extend(word);
+        }
+        if let Some(verifying_contract) = self.verifying_contract {
+            encoded.extend([0u8; 12]);
+            encoded.extend(verifying_contract.as_bytes());
+        }
+        if let Some(salt) = self.salt {
+            encoded.extend(salt.as_bytes());
+        }
+        H256(keccak256(&encoded))
+    }
+}
+
+impl TypedDataField {
+    pub fn new(name: &str, kind: &str) -> Self {
+        TypedDataField {
+            name: name.to_owned(),
+            kind: kind.to_owned(),
+        }
+    }
+}
+
+impl TypedData {
+    /// Start building typed data, the `EIP712Domain` type is derived from `domain`.
+    pub fn new(primary_type: &str, domain: Eip712Domain, message: Value) -> Self {
+        let mut types = BTreeMap::new();
+        types.insert("EIP712Domain".to_owned(), domain.fields());
+        TypedData {
+            types,
+            primary_type: primary_type.to_owned(),
+            domain,
+            message,
+        }
+    }
+
+    /// Add a struct type, with `fields` as `(name, type)` pairs.
+    pub fn with_type(mut self, name: &str, fields: &[(&str, &str)]) -> Self {
+        let fields = fields
+            .iter()
+            .map(|(name, kind)| TypedDataField::new(name, kind))
+            .collect();
+        self.types.insert(name.to_owned(), fields);
+        self
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
@@ -0,0 +1,176 @@
+use async_channel::{unbounded, Receiver, Sender};
+use bevy::prelude::*;
+use serde_json::json;
+use std::{cell::RefCell, time::Duration};
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web3::{transports::eip_1193::Provider, types::H160};
+
+use crate::{transport::js_error, EthWallet, TransportConfig, WalletConfig, WalletOp};
+
+// Requires the `@walletconnect/ethereum-provider` npm package in the bundle.
+#[wasm_bindgen(module = "@walletconnect/ethereum-provider")]
+extern "C" {
+    type EthereumProvider;
+
+    #[wasm_bindgen(static_method_of = EthereumProvider, js_name = init)]
+    fn init(opts: &JsValue) -> js_sys::Promise;
+
+    #[wasm_bindgen(method)]
+    fn on(this: &EthereumProvider, event: &str, listener: &Closure<dyn FnMut(JsValue)>);
+
+    #[wasm_bindgen(method, js_name = removeListener)]
+    fn remove_listener(
+        this: &EthereumProvider,
+        event: &str,
+        listener: &Closure<dyn FnMut(JsValue)>,
+    );
+
+    #[wasm_bindgen(method)]
+    fn enable(this: &EthereumProvider) -> js_sys::Promise;
+}
+
+thread_local! {
+    static PROVIDER: RefCell<Option<Session>> = const { RefCell::new(None) };
+}
+
+/// A session and the `display_uri` listener it calls back, kept alive as
+/// long as the session and detached when it is replaced.
+struct Session {
+    provider: Provider,
+    on_uri: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let provider: &EthereumProvider = self.provider.unchecked_ref();
+        provider.remove_listener("display_uri", &self.on_uri);
+    }
+}
+
+/// How long `WalletConnect::connect` waits for the user to scan the pairing
+/// uri and approve, much longer than a wallet popup.
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The EIP-1193 provider of the established session, if any.
+pub(crate) fn provider() -> Option<Provider> {
+    PROVIDER.with(|p| p.borrow().as_ref().map(|session| session.provider.clone()))
+}
+
+/// Routes `EthWallet` through a WalletConnect v2 session instead of the
+/// injected wallet. Requires `WalletPlugin`, added before it, whose settings
+/// are kept. Start the session with `WalletConnect::connect`.
+pub struct WalletConnectPlugin {
+    pub project_id: String,
+    pub chains: Vec<u64>,
+}
+
+impl Plugin for WalletConnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.world
+            .get_resource_mut::<WalletConfig>()
+            .expect("WalletConnectPlugin requires WalletPlugin, add it first")
+            .transport = TransportConfig::WalletConnect;
+
+        let (state_tx, state_rx) = unbounded();
+        app.insert_resource(WalletConnect {
+            project_id: self.project_id.clone(),
+            chains: self.chains.clone(),
+            uri: None,
+            state_tx,
+            state_rx,
+        })
+        .add_event::<WalletConnectStateChanged>()
+        .add_systems(PreUpdate, emit_wallet_connect_events);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WalletConnectState {
+    /// Pairing uri, render it as a QR code for the mobile wallet to scan.
+    Uri(String),
+    Connected,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct WalletConnectStateChanged(pub WalletConnectState);
+
+#[derive(Resource)]
+pub struct WalletConnect {
+    pub project_id: String,
+    pub chains: Vec<u64>,
+    uri: Option<String>,
+    state_tx: Sender<WalletConnectState>,
+    state_rx: Receiver<WalletConnectState>,
+}
+
+impl WalletConnect {
+    /// Start a session. The pairing uri is delivered first, then once the wallet
+    /// approves, `Connected` and the accounts (as with `EthWallet::connect`).
+    pub fn connect(&self, wallet: &EthWallet) {
+        let opts = json!({
+            "projectId": self.project_id,
+            "chains": self.chains,
+            "showQrModal": false,
+        });
+        let state_tx = self.state_tx.clone();
+        wallet.spawn_within(
+            WalletOp::WalletConnect,
+            PAIRING_TIMEOUT,
+            wallet.account_tx.clone(),
+            move |transports| async move {
+                let opts = js_sys::JSON::parse(&opts.to_string()).map_err(js_error)?;
+                let provider: EthereumProvider = JsFuture::from(EthereumProvider::init(&opts))
+                    .await
+                    .map_err(js_error)?
+                    .unchecked_into();
+
+                let uri_tx = state_tx.clone();
+                let on_uri = Closure::wrap(Box::new(move |uri: JsValue| {
+                    if let Some(uri) = uri.as_string() {
+                        let _ = uri_tx.try_send(WalletConnectState::Uri(uri));
+                    }
+                }) as Box<dyn FnMut(JsValue)>);
+                provider.on("display_uri", &on_uri);
+                let enable = provider.enable();
+                // detaches the listener if the user never approves
+                let session = Session {
+                    provider: provider.unchecked_into(),
+                    on_uri,
+                };
+
+                JsFuture::from(enable).await.map_err(js_error)?;
+                // the previous session is dropped
+                PROVIDER.with(|p| *p.borrow_mut() = Some(session));
+                let _ = state_tx.send(WalletConnectState::Connected).await;
+
+                let web3 = transports.web3(&TransportConfig::WalletConnect)?;
+                let addrs: Vec<H160> = web3.eth().accounts().await?;
+                let chain = web3.eth().chain_id().await?;
+                Ok(Some((addrs, chain.as_u64())))
+            },
+        );
+    }
+
+    /// The pairing uri of the pending session.
+    pub fn uri(&self) -> Option<&str> {
+        self.uri.as_deref()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        provider().is_some()
+    }
+}
+
+fn emit_wallet_connect_events(
+    mut session: ResMut<WalletConnect>,
+    mut events: EventWriter<WalletConnectStateChanged>,
+) {
+    while let Ok(state) = session.state_rx.try_recv() {
+        match &state {
+            WalletConnectState::Uri(uri) => session.uri = Some(uri.clone()),
+            WalletConnectState::Connected => session.uri = None,
+        }
+        events.send(WalletConnectStateChanged(state));
+    }
+}
@@ -0,0 +1,355 @@
+use async_channel::{unbounded, Receiver, Sender};
+use bevy::prelude::*;
+use futures_timer::Delay;
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use web3::{
+    futures::{
+        future::{BoxFuture, FutureExt, Shared},
+        StreamExt,
+    },
+    transports::WebSocket,
+    types::{BlockHeader, Filter, Log, H256, U256, U64},
+    Transport, Web3,
+};
+
+use crate::{EthWallet, RecvError, WalletConfig, WalletOp, Web3Error};
+
+/// Connects to a `ws://` or `wss://` node at startup, for subscriptions.
+/// Requires `WalletPlugin`, connection failures are reported through `EthWallet::recv_error`.
+pub struct WsWalletPlugin {
+    pub url: String,
+}
+
+impl WsWalletPlugin {
+    pub fn new(url: &str) -> Self {
+        WsWalletPlugin {
+            url: url.to_owned(),
+        }
+    }
+}
+
+impl Plugin for WsWalletPlugin {
+    fn build(&self, app: &mut App) {
+        let (tx, rx) = unbounded();
+        app.insert_resource(WsEndpoint {
+            url: self.url.clone(),
+        })
+        .insert_resource(WsConnection { web3: None, tx, rx })
+        .add_event::<WsConnected>()
+        .add_event::<NewHead>()
+        .add_systems(PostStartup, connect_ws)
+        .add_systems(PreUpdate, recv_ws);
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct WsEndpoint {
+    pub url: String,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct WsConnected;
+
+#[derive(Event, Clone, Debug)]
+pub struct NewHead(pub BlockHeader);
+
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Bound of the startup connection, independent of `OperationPolicy::timeout`
+/// which may be tuned short for reads.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The websocket connection, once established. It is replaced when a
+/// subscription reconnects after the socket dropped, `WsConnected` is fired again.
+#[derive(Resource)]
+pub struct WsConnection {
+    web3: Option<Web3<WebSocket>>,
+    tx: Sender<Web3<WebSocket>>,
+    rx: Receiver<Web3<WebSocket>>,
+}
+
+impl WsConnection {
+    pub fn web3(&self) -> Option<&Web3<WebSocket>> {
+        self.web3.as_ref()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.web3.is_some()
+    }
+}
+
+type Connecting = Shared<BoxFuture<'static, Result<Web3<WebSocket>, web3::Error>>>;
+
+/// The connection `WsWalletPlugin` opens, shared by the subscriptions.
+struct Connection {
+    url: String,
+    /// Bumped each time the connection is replaced.
+    generation: u64,
+    web3: Connecting,
+    /// Where the replacements go, see `WsConnection`.
+    tx: Sender<Web3<WebSocket>>,
+}
+
+/// The websocket state of `EthWallet`.
+pub(crate) struct WsState {
+    connection: Arc<Mutex<Option<Connection>>>,
+    head_tx: Sender<BlockHeader>,
+    head_rx: Receiver<BlockHeader>,
+}
+
+impl Default for WsState {
+    fn default() -> Self {
+        let (head_tx, head_rx) = unbounded();
+        WsState {
+            connection: Arc::default(),
+            head_tx,
+            head_rx,
+        }
+    }
+}
+
+/// The shared connection once established. If it is the `stale` one (it
+/// dropped), it is replaced by a new one, connected with backoff.
+async fn connection(
+    shared: &Mutex<Option<Connection>>,
+    stale: &mut Option<u64>,
+) -> Result<(u64, Web3<WebSocket>), Web3Error> {
+    loop {
+        let (generation, web3) = {
+            let mut shared = shared.lock().unwrap();
+            let connection = shared.as_mut().ok_or_else(|| {
+                Web3Error::InvalidParams("no websocket endpoint, add WsWalletPlugin".to_owned())
+            })?;
+            if Some(connection.generation) == *stale {
+                let (url, tx) = (connection.url.clone(), connection.tx.clone());
+                connection.generation += 1;
+                connection.web3 = async move {
+                    let web3 = reconnect(&url).await;
+                    let _ = tx.send(web3.clone()).await;
+                    Ok(web3)
+                }
+                .boxed()
+                .shared();
+            }
+            (connection.generation, connection.web3.clone())
+        };
+        match web3.await {
+            Ok(web3) => return Ok((generation, web3)),
+            // the startup connection failed, it was reported by `connect_ws`
+            Err(_) => *stale = Some(generation),
+        }
+    }
+}
+
+impl EthWallet {
+    /// Push logs matching `filter` with `eth_subscribe` on the connection of
+    /// `WsWalletPlugin`, delivered like `watch_logs` through `recv_log` and
+    /// `LogReceived`. The subscription reconnects with backoff when the socket
+    /// drops, and fetches the logs missed in the meantime, each delivered once.
+    pub fn subscribe_logs(&self, filter: Filter) {
+        let shared = self.ws.connection.clone();
+        let log_tx = self.log_tx.clone();
+        self.spawn_untimed(WalletOp::WsSubscribe, log_tx.clone(), move |_| async move {
+            let mut delivered = Delivered::default();
+            let mut subscribed = false;
+            let mut stale = None;
+            loop {
+                let (generation, web3) = connection(&shared, &mut stale).await?;
+                match stream_logs(&web3, &filter, &mut delivered, &mut subscribed, &log_tx).await {
+                    Ok(()) => return Ok(None),
+                    // the filter was rejected, retrying won't help
+                    Err(e) if !subscribed => return Err(e.into()),
+                    Err(e) => warn!("log subscription dropped: {e}"),
+                }
+                stale = Some(generation);
+                Delay::new(MIN_BACKOFF).await;
+            }
+        });
+    }
+
+    /// Push new blocks with `eth_subscribe` on the connection of
+    /// `WsWalletPlugin`, delivered through `recv_head` and `NewHead`.
+    /// Reconnects with backoff when the socket drops.
+    pub fn subscribe_new_heads(&self) {
+        let shared = self.ws.connection.clone();
+        let head_tx = self.ws.head_tx.clone();
+        self.spawn_untimed(
+            WalletOp::WsSubscribe,
+            head_tx.clone(),
+            move |_| async move {
+                let mut subscribed = false;
+                let mut stale = None;
+                loop {
+                    let (generation, web3) = connection(&shared, &mut stale).await?;
+                    match stream_heads(&web3, &mut subscribed, &head_tx).await {
+                        Ok(()) => return Ok(None),
+                        // the node doesn't support it, retrying won't help
+                        Err(e) if !subscribed => return Err(e.into()),
+                        Err(e) => warn!("head subscription dropped: {e}"),
+                    }
+                    stale = Some(generation);
+                    Delay::new(MIN_BACKOFF).await;
+                }
+            },
+        );
+    }
+
+    pub fn recv_head(&self) -> Result<BlockHeader, RecvError> {
+        Ok(self.ws.head_rx.try_recv()?)
+    }
+}
+
+/// Connect to `url`, retrying with exponential backoff until it succeeds.
+async fn reconnect(url: &str) -> Web3<WebSocket> {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match WebSocket::new(url).await {
+            Ok(transport) => return Web3::new(transport),
+            Err(e) => {
+                warn!("websocket {url} unavailable: {e}, retrying in {backoff:?}");
+                Delay::new(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+fn closed() -> web3::Error {
+    web3::Error::Transport(web3::error::TransportError::Message(
+        "subscription closed".to_owned(),
+    ))
+}
+
+/// Forward the heads of one connection, `Ok` once nobody listens anymore.
+async fn stream_heads(
+    web3: &Web3<WebSocket>,
+    subscribed: &mut bool,
+    tx: &Sender<BlockHeader>,
+) -> web3::Result<()> {
+    let mut stream = web3.eth_subscribe().subscribe_new_heads().await?;
+    *subscribed = true;
+    while let Some(head) = stream.next().await {
+        if tx.send(head?).await.is_err() {
+            return Ok(());
+        }
+    }
+    Err(closed())
+}
+
+/// The logs of a subscription delivered from its last block, which the
+/// catch-up after a reconnect fetches again.
+#[derive(Default)]
+struct Delivered {
+    block: Option<U64>,
+    logs: HashSet<(Option<H256>, Option<U256>)>,
+}
+
+impl Delivered {
+    /// Record `log`, `false` if it was delivered already.
+    fn insert(&mut self, log: &Log) -> bool {
+        if log.block_number > self.block {
+            self.block = log.block_number;
+            self.logs.clear();
+        }
+        self.logs.insert((log.block_hash, log.log_index))
+    }
+}
+
+/// Forward the logs of one connection, `Ok` once nobody listens anymore.
+async fn stream_logs(
+    web3: &Web3<WebSocket>,
+    filter: &Filter,
+    delivered: &mut Delivered,
+    subscribed: &mut bool,
+    tx: &Sender<Log>,
+) -> web3::Result<()> {
+    let mut stream = web3.eth_subscribe().subscribe_logs(filter.clone()).await?;
+    *subscribed = true;
+
+    // subscriptions only push new logs, catch up on the ones missed while
+    // disconnected, from the last block as it may have had more. The
+    // subscription is opened first so none are lost in between, it may then
+    // push some of the caught up logs again.
+    let mut caught_up = HashSet::new();
+    if let Some(block) = delivered.block {
+        for log in logs_from(web3, filter, block).await? {
+            caught_up.insert((log.block_hash, log.log_index));
+            if !delivered.insert(&log) {
+                continue;
+            }
+            if tx.send(log).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    while let Some(log) = stream.next().await {
+        let log = log?;
+        if caught_up.remove(&(log.block_hash, log.log_index)) || !delivered.insert(&log) {
+            continue;
+        }
+        if tx.send(log).await.is_err() {
+            return Ok(());
+        }
+    }
+    Err(closed())
+}
+
+/// `eth_getLogs` for `filter` starting at `block`, `Filter` fields are
+/// private so it is patched as JSON.
+async fn logs_from(web3: &Web3<WebSocket>, filter: &Filter, block: U64) -> web3::Result<Vec<Log>> {
+    let mut params = json!(filter);
+    params["fromBlock"] = json!(block);
+    let logs = web3
+        .transport()
+        .execute("eth_getLogs", vec![params])
+        .await?;
+    serde_json::from_value(logs).map_err(|e| web3::Error::Decoder(e.to_string()))
+}
+
+fn connect_ws(endpoint: Res<WsEndpoint>, connection: Res<WsConnection>, wallet: Res<EthWallet>) {
+    let url = endpoint.url.clone();
+    let connect = url.clone();
+    let web3: Connecting = async move { Ok(Web3::new(WebSocket::new(&connect).await?)) }
+        .boxed()
+        .shared();
+    *wallet.ws.connection.lock().unwrap() = Some(Connection {
+        url,
+        generation: 0,
+        web3: web3.clone(),
+        tx: connection.tx.clone(),
+    });
+
+    let tx = connection.tx.clone();
+    wallet.spawn_within(
+        WalletOp::WsConnect,
+        CONNECT_TIMEOUT,
+        tx,
+        move |_| async move { Ok(Some(web3.await?)) },
+    );
+}
+
+fn recv_ws(
+    config: Res<WalletConfig>,
+    wallet: Res<EthWallet>,
+    mut connection: ResMut<WsConnection>,
+    mut events: EventWriter<WsConnected>,
+    mut heads: EventWriter<NewHead>,
+) {
+    while let Ok(web3) = connection.rx.try_recv() {
+        connection.web3 = Some(web3);
+        events.send(WsConnected);
+    }
+    // like the wallet results, heads wait for `recv_head` unless events are on
+    if !config.emit_events {
+        return;
+    }
+    while let Ok(head) = wallet.recv_head() {
+        heads.send(NewHead(head));
+    }
+}